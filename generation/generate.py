@@ -40,6 +40,10 @@ def extract_from_pdf(pdf_filename, output_filename, render_dpi, downscale_percen
     #######################################################################################
     return 0
 
+def iso_to_latex_date(iso_date):
+    ## Converts a YYYY-MM-DD date to the DD/MM/YYYY form that \SetDate expects.
+    return datetime.date.fromisoformat(iso_date).strftime("%d/%m/%Y")
+
 if __name__ == "__main__":
     ##################################ARGPARSE#############################################
     parser = argparse.ArgumentParser(description="Compiles a single glyph/ambigram challenge image and outputs as png. Requires LaTeX installation, pdftoppm and imagemagick.")
@@ -47,7 +51,10 @@ if __name__ == "__main__":
     parser.add_argument("-o", "--out", type=str, default=None, help="name of the output png (if unspecified, will follow the name of the chosen subcommand e.g. glyph_announcement.png)", metavar="FILE")
     parser.add_argument("--start_date", type=str, default=None, help="date of beginning of challenge")
     parser.add_argument("--end_date", type=str, default=None, help="date of end of challenge")
+    parser.add_argument("--start_date_iso", type=str, default=None, help="date of beginning of challenge as YYYY-MM-DD; takes precedence over --start_date")
+    parser.add_argument("--end_date_iso", type=str, default=None, help="date of end of challenge as YYYY-MM-DD; takes precedence over --end_date")
     parser.add_argument("--week", type=int, default=None, help="current week number")
+    parser.add_argument("--jobname", type=str, default="weekly_challenges", help="base name of the intermediate .tex and .pdf files, so that concurrent runs don't clobber each other")
     subcommands = parser.add_subparsers(title="subcommands", description="run ``<SUBCOMMAND> --help`` for that subcommand's usage", required=True, dest="subcommand")
     glyph_announcement = subcommands.add_parser("glyph_announcement", help="glyph_announcement [-size_percentage PERCENT] <GLYPH>")
     glyph_announcement.add_argument("glyph", help="the glyph to be announced")
@@ -118,17 +125,20 @@ if __name__ == "__main__":
         week_colour = "Cyan"
     else:
         week_colour = "Red"
-    date_formatted = args.start_date
+    #the iso dates are unambiguous, so prefer them over the human-readable ones when given
+    start_date = iso_to_latex_date(args.start_date_iso) if args.start_date_iso else args.start_date
+    end_date = iso_to_latex_date(args.end_date_iso) if args.end_date_iso else args.end_date
+    date_formatted = start_date
     ########################FILE STUFF##########################
     with open("weekly_challenges_base.tex", "r", encoding='utf8') as f:
         contents = f.read()
-    with open("weekly_challenges.tex", "w", encoding='utf8') as f:
+    with open(f"{args.jobname}.tex", "w", encoding='utf8') as f:
         f.write(contents)
         f.writelines(
 fr"""
-\SetDate[{args.start_date}]
+\SetDate[{start_date}]
 \SaveDate[\StartDate]
-\SetDate[{args.end_date}]
+\SetDate[{end_date}]
 \SaveDate[\EndDate]
 \WeekColor{{{week_colour}}}
 """)
@@ -336,7 +346,7 @@ fr"""
 """)
     ##################################COMPILATION##########################################
     print("Compiling LaTeX code...")
-    process = subprocess.Popen(["xelatex", "-interaction=nonstopmode", "weekly_challenges.tex"],
+    process = subprocess.Popen(["xelatex", "-interaction=nonstopmode", f"{args.jobname}.tex"],
                                 stdout=subprocess.PIPE,
                                 stderr=subprocess.PIPE,
                                 universal_newlines=True)
@@ -344,4 +354,4 @@ fr"""
     if latex_return != 0:
         print("LaTeX exited with an error, exiting...")
         sys.exit(latex_return)
-    sys.exit(extract_from_pdf("weekly_challenges.pdf", args.out or args.subcommand, RENDER_DPI, DOWNSCALE_PERCENTAGE, args.verbose))
+    sys.exit(extract_from_pdf(f"{args.jobname}.pdf", args.out or args.subcommand, RENDER_DPI, DOWNSCALE_PERCENTAGE, args.verbose))
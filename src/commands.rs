@@ -1,16 +1,24 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
 use chrono::{DateTime, Duration, Utc};
 use poise::builtins::register_application_commands;
+use rand::{rngs::StdRng, SeedableRng};
 use poise::{ChoiceParameter, CreateReply};
-use poise::serenity_prelude::{CreateAttachment, CreateEmbed, CreateEmbedAuthor};
+use poise::serenity_prelude::{Attachment, ButtonStyle, ComponentInteractionCollector, CreateActionRow, CreateAttachment, CreateButton, CreateEmbed, CreateEmbedAuthor, CreateInteractionResponse, CreateMessage, ReactionType, User};
 use tokio::time;
-use crate::{info, sql, Context, Res, ResT};
-use crate::core::{create_embed, file_mtime, handle_command_error};
-use crate::sql::{add_prompt, edit_prompt, forecast_prompt_details, get_current_week_num, get_prompt_data, get_prompt_id, get_prompt_id_data, get_week_info, swap_prompts};
-use crate::types::{Challenge, ChallengeImageOptions::*, PreviewableImages, PromptData, UploadableImages};
-use crate::file::generate_challenge_image;
+use tracing::instrument;
+use crate::{err, info, sql, Context, Res, ResT};
+use crate::core::{create_challenge_embed, create_embed, file_mtime, handle_command_error, maintenance_mode, message_link, safe_truncate, set_maintenance_mode};
+use crate::sql::{add_prompt, edit_prompt, forecast_prompt_details, forecast_queue, forecast_queue_details, get_current_week_num, get_prompt_id, get_prompt_id_data, get_user_profile, get_submissions, get_votes, get_week_info, move_prompt, rank_winners, shuffle_prompts, swap_prompts, tally_week, voting_concluded};
+use crate::types::{normalise_prompt_string, parse_start_date, Challenge, ChallengeImageOptions::*, ImportMode, LeaderboardMetric, PreviewableImages, PromptData, Timestamp, UploadableImages, WeekInfo, WinnerPosition, NULL_TIMESTAMP};
+use crate::file::{check_generation_script, clear_image_cache, delete_submission, download_submission, generate_challenge_image, initialise_submissions_directory, prune_submission_images, render_challenge_image, save_image_override, submission_exists, take_challenge_image};
+use crate::scheduling::{advance_challenge, announce_now, extend_week, last_scheduler_run};
+use crate::config::config;
+use crate::server_data::{format_poll_spiel, VOTING_EMOJI_SEQUENCE};
 
 /// Edit your nickname.
 #[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
 pub async fn nickname(
     ctx: Context<'_>,
     name: String,
@@ -28,7 +36,61 @@ pub async fn nickname(
     Ok(())
 }
 
-/// Display your user profile.
+/// Choose whether to be sent a summary of your votes when voting ends.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn vote_summaries(
+    ctx: Context<'_>,
+    #[description = "Whether to send you a DM with your votes and the top 3 when voting ends"] enabled: bool,
+) -> Res {
+    sql::set_vote_summaries(ctx.author().id, enabled).await?;
+    ctx.say(if enabled { "You will be sent a summary of your votes when voting ends." }
+        else { "You will no longer be sent summaries of your votes." }).await?;
+    Ok(())
+}
+
+/// Withdraw your most recent submission to the current week of a challenge.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn withdraw(
+    ctx: Context<'_>,
+    #[description = "The challenge to withdraw your submission from"] challenge: Challenge,
+) -> Res {
+    if maintenance_mode() {
+        return Err("Glyfi is down for maintenance, so submissions can't be withdrawn right now. Please try again later.".into());
+    }
+    let week_num = get_current_week_num(challenge).await?;
+    if get_week_info(week_num, challenge).await?.actual_end_time != NULL_TIMESTAMP {
+        return Err(format!("Week {} of the {} challenge has already ended.", week_num, challenge.name()).into());
+    }
+    let user_id = ctx.author().id;
+    let Some((_, message_id)) = get_submissions(challenge, week_num).await?.into_iter().filter(|(author, _)| *author == user_id).last() else {
+        return Err(format!("You have no submission to week {} of the {} challenge.", week_num, challenge.name()).into());
+    };
+
+    info!("Withdrawing submission {} from {} for challenge {:?}", message_id, user_id, challenge);
+    sql::deregister_submission(message_id, challenge, week_num).await?;
+    delete_submission(message_id, challenge, week_num).await?;
+
+    // Remove the reactions too, so the message doesn't look like it is still submitted. This
+    // is allowed to fail, e.g. if the message was deleted in the meantime.
+    if let Ok(message) = challenge.submission_channel().message(&ctx, message_id).await {
+        let me = ctx.cache().current_user().id;
+        for reaction in &message.reactions {
+            if reaction.me && config().confirm_emoji == reaction.reaction_type {
+                let _ = message.delete_reaction(&ctx, Some(me), reaction.reaction_type.clone()).await;
+            } else if matches!(reaction.reaction_type, ReactionType::Custom { id, .. } if id == config().submit_emoji_id) {
+                let _ = message.delete_reaction(&ctx, Some(user_id), reaction.reaction_type.clone()).await;
+            }
+        }
+    }
+
+    ctx.say(format!("Withdrew your submission {} from week {} of the {} challenge.",
+        message_link(config().server_id, challenge.submission_channel(), message_id), week_num, challenge.name())).await?;
+    Ok(())
+}
+
+/// Display your or another user’s profile.
 //
 // Shows the specified user profile or the user that executes it. Shows
 // the user’s UserID, nickname, amount of glyphs submitted, amount of
@@ -36,41 +98,39 @@ pub async fn nickname(
 // highest ranking in ambigram challenge, & amount of 1st, 2nd, and
 // 3rd place placements.
 #[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error")]
-pub async fn profile(ctx: Context<'_>) -> Res {
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn profile(
+    ctx: Context<'_>,
+    #[description = "The user whose profile to show - defaults to yourself"] user: Option<User>,
+) -> Res {
     const ZWSP: &str = "\u{200B}";
 
-    let data = sql::get_user_profile(ctx.author().id).await?;
+    // Users without a row in the database just get an empty profile.
+    let user = user.as_ref().unwrap_or(ctx.author());
+    let data = sql::get_user_profile(user.id).await?;
     let name: &str = data.nickname.as_ref()
-        .or(ctx.author().global_name.as_ref())
-        .unwrap_or(&ctx.author().name)
+        .or(user.global_name.as_ref())
+        .unwrap_or(&user.name)
         .as_str();
 
     let mut embed = create_embed(&ctx);
     embed = embed.author(CreateEmbedAuthor::new(format!("{}’s Profile", name))
-        .icon_url(ctx.author().face())
+        .icon_url(user.face())
     );
 
-    // Helper to add a field.
-    fn add(embed: CreateEmbed, name: &'static str, value: i64) -> CreateEmbed {
-        embed.field(
-            name,
-            format!(
-                "{} time{}",
-                value,
-                if value == 1 { "" } else { "s" }
-            ),
-            true,
-        )
+    // Helper to describe the placements in one challenge. Users who haven't placed in the
+    // top three get their highest ranking instead.
+    fn placements(first: i64, second: i64, third: i64, highest_ranking: i64) -> String {
+        let times = |value: i64| format!("{} time{}", value, if value == 1 { "" } else { "s" });
+        if first != 0 || second != 0 || third != 0 {
+            format!("1st Place: {}\n2nd Place: {}\n3rd Place: {}", times(first), times(second), times(third))
+        } else if highest_ranking != 0 {
+            format!("Highest ranking: {}", highest_ranking)
+        } else {
+            "No placements yet".to_owned()
+        }
     }
 
-    let have_glyphs_rating = data.glyphs_first != 0 ||
-        data.glyphs_second != 0 ||
-        data.glyphs_third != 0;
-
-    let have_ambigrams_rating = data.ambigrams_first != 0 ||
-        data.ambigrams_second != 0 ||
-        data.ambigrams_third != 0;
-
     // Add submissions.
     if data.glyphs_submissions != 0 || data.ambigrams_submissions != 0 {
         embed = embed.field("Submitted Glyphs", format!("{}", data.glyphs_submissions), true);
@@ -78,30 +138,56 @@ pub async fn profile(ctx: Context<'_>) -> Res {
         embed = embed.field(ZWSP, ZWSP, true); // Empty field.
     }
 
-    // Add first/second/third place ratings for glyphs challenge.
-    if have_glyphs_rating {
-        embed = add(embed, "1st Place – G", data.glyphs_first);
-        embed = add(embed, "2nd Place – G", data.glyphs_second);
-        embed = add(embed, "3nd Place – G", data.glyphs_third);
-    } else {
-        embed = embed.field(
-            "Highest ranking in Glyphs Challenge",
-            format!("{}", data.highest_ranking_glyphs),
-            false,
-        );
-    }
-
-    // Add first/second/third place for ambigrams challenge.
-    if have_ambigrams_rating {
-        embed = add(embed, "1st Place – A", data.ambigrams_first);
-        embed = add(embed, "2nd Place – A", data.ambigrams_second);
-        embed = add(embed, "3nd Place – A", data.ambigrams_third);
+    // Add placements, one field per challenge so the embed stays well within
+    // Discord's limit of 25 fields.
+    embed = embed.field("Glyphs Challenge", placements(data.glyphs_first, data.glyphs_second,
+        data.glyphs_third, data.highest_ranking_glyphs), true);
+    embed = embed.field("Ambigrams Challenge", placements(data.ambigrams_first, data.ambigrams_second,
+        data.ambigrams_third, data.highest_ranking_ambigrams), true);
+    embed = embed.field(ZWSP, ZWSP, true); // Empty field.
+
+    ctx.send(CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+/// Show the users with the most wins or submissions.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn leaderboard(
+    ctx: Context<'_>,
+    #[description = "What to rank users by"] metric: LeaderboardMetric,
+    #[description = "Only count this challenge - defaults to both"] challenge: Option<Challenge>,
+) -> Res {
+    const LEADERBOARD_SIZE: i64 = 10;
+
+    let entries = sql::get_leaderboard(metric, challenge, LEADERBOARD_SIZE).await?;
+    let title = match challenge {
+        Some(c) => format!("{} Challenge Leaderboard", c.name()),
+        None => "Leaderboard".to_owned(),
+    };
+
+    let mut embed = match challenge {
+        Some(c) => create_challenge_embed(&ctx, c),
+        None => create_embed(&ctx),
+    }.author(CreateEmbedAuthor::new(title));
+    if entries.is_empty() {
+        embed = embed.description("Nobody is on this leaderboard yet.");
     } else {
-        embed = embed.field(
-            "Highest ranking in Ambigrams Challenge",
-            format!("{}", data.highest_ranking_ambigrams),
-            false,
-        );
+        let unit = match metric {
+            LeaderboardMetric::FirstPlaces => "first place",
+            LeaderboardMetric::Submissions => "submission",
+        };
+
+        // Fall back to a mention if the user hasn’t set a nickname.
+        let lines: Vec<String> = entries.into_iter().enumerate().map(|(idx, (user, nickname, score))| format!(
+            "**{}.** {} – {} {}{}",
+            idx + 1,
+            nickname.unwrap_or_else(|| format!("<@{}>", user)),
+            score,
+            unit,
+            if score == 1 { "" } else { "s" }
+        )).collect();
+        embed = embed.description(safe_truncate(lines.join("\n"), 4096));
     }
 
     ctx.send(CreateReply::default().embed(embed)).await?;
@@ -109,12 +195,40 @@ pub async fn profile(ctx: Context<'_>) -> Res {
 }
 
 #[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error",
- subcommands("queue_add", "queue_list", "queue_remove", "queue_preview", "queue_edit", "queue_swap", "queue_move"), 
+ subcommands("queue_add", "queue_list", "queue_schedule", "queue_remove", "queue_preview", "queue_edit", "queue_swap", "queue_move", "queue_export", "queue_import", "queue_insert", "queue_clear", "queue_undo", "queue_skip", "queue_shuffle", "queue_when"), 
  default_member_permissions = "ADMINISTRATOR")]
 pub async fn queue(_ctx: Context<'_>) -> Res { unreachable!(); }
 
+/// How many previous states of each queue `/queue undo` can go back to.
+const QUEUE_HISTORY_LEN: usize = 10;
+
+/// Previous states of each queue, oldest first. This is only kept in memory, so the
+/// history is lost when the bot restarts.
+static QUEUE_HISTORY: OnceLock<Mutex<HashMap<Challenge, Vec<Vec<PromptData>>>>> = OnceLock::new();
+
+/// Remember the state of a queue from before a successful modification, so it can be undone.
+fn push_queue_history(challenge: Challenge, queue: Vec<PromptData>) {
+    let mut history = QUEUE_HISTORY.get_or_init(Default::default).lock().unwrap();
+    let states = history.entry(challenge).or_default();
+    states.push(queue);
+    if states.len() > QUEUE_HISTORY_LEN { states.remove(0); }
+}
+
+/// Take the most recent remembered state of a queue.
+fn pop_queue_history(challenge: Challenge) -> Option<Vec<PromptData>> {
+    QUEUE_HISTORY.get_or_init(Default::default).lock().unwrap().get_mut(&challenge)?.pop()
+}
+
+/// Remove the cached images of a queue after some of its prompts were changed or removed.
+async fn invalidate_queue_images(challenge: Challenge) {
+    if let Err(e) = clear_image_cache(challenge).await {
+        err!("Error clearing image cache for {}: {}", challenge.name(), e);
+    }
+}
+
 /// Add a new prompt to the given queue.
 #[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "add", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
 pub async fn queue_add(
     ctx: Context<'_>,
     #[description = "Which challenge to set the prompt for"] challenge: Challenge,
@@ -122,92 +236,143 @@ pub async fn queue_add(
     #[description = "Percentage modifying the size of the prompt - defaults to 100 (normal size)"] size_percentage: Option<u16>,
     #[description = "Duration of the challenge measured in weeks - defaults to 1"] custom_duration: Option<u16>,
     #[description = "Whether the week is special - defaults to false"] is_special: Option<bool>,
-    #[description = "Any extra text to accompany the announcement of this glyph"] extra_announcement_text: Option<String>
+    #[description = "Any extra text to accompany the announcement of this glyph"] extra_announcement_text: Option<String>,
+    #[description = "Don't start this prompt before this date (YYYY-MM-DD, UTC)"] fixed_start_date: Option<String>,
+    #[description = "Add the prompt even if it is already queued or has been used before - defaults to false"] force: Option<bool>
 ) -> Res {
-    if let Some(0) = size_percentage { return Err("Cannot set size_percentage to 0.".into()); }
-    if let Some(0) = custom_duration { return Err("Cannot set custom_duration to 0.".into()); }
-    let prompt_data = PromptData { challenge, prompt_string, size_percentage: size_percentage.filter(|x| x != &100), 
-        custom_duration, is_special: is_special.filter(|x| x == &true), extra_announcement_text };
+    let prompt_data = PromptData::new(challenge, &prompt_string, size_percentage, custom_duration,
+        is_special, extra_announcement_text, fixed_start_date.as_deref())?;
+    if !force.unwrap_or(false) { check_duplicate_prompt(challenge, &prompt_data.prompt_string, None).await?; }
 
-    // Save prompt.
+    // Save prompt. We already know where it ends up, so forecast from the queue we
+    // have in memory rather than reading it back.
+    let mut queue = sql::get_prompts(challenge).await?;
     add_prompt(&prompt_data).await?;
+    push_queue_history(challenge, queue.clone());
+    queue.push(prompt_data.clone());
+
+    send_queue_preview(ctx, challenge, &queue, -1, "Successfully added entry!").await
+}
+
+/// Insert a new prompt into the given queue at a specific position.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "insert", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn queue_insert(
+    ctx: Context<'_>,
+    #[description = "Which challenge to set the prompt for"] challenge: Challenge,
+    #[description = "Position in the queue to insert the prompt at - inserting past the end appends it"] position: usize,
+    #[description = "The prompt for the challenge"] prompt_string: String,
+    #[description = "Percentage modifying the size of the prompt - defaults to 100 (normal size)"] size_percentage: Option<u16>,
+    #[description = "Duration of the challenge measured in weeks - defaults to 1"] custom_duration: Option<u16>,
+    #[description = "Whether the week is special - defaults to false"] is_special: Option<bool>,
+    #[description = "Any extra text to accompany the announcement of this glyph"] extra_announcement_text: Option<String>,
+    #[description = "Don't start this prompt before this date (YYYY-MM-DD, UTC)"] fixed_start_date: Option<String>,
+    #[description = "Add the prompt even if it is already queued or has been used before - defaults to false"] force: Option<bool>
+) -> Res {
+    if position == 0 { return Err("0 is not a valid prompt position.".into()); }
+    let prompt_data = PromptData::new(challenge, &prompt_string, size_percentage, custom_duration,
+        is_special, extra_announcement_text, fixed_start_date.as_deref())?;
+    if !force.unwrap_or(false) { check_duplicate_prompt(challenge, &prompt_data.prompt_string, None).await?; }
+
+    info!("Inserting prompt {:?} at {}:{} in db...", prompt_data, challenge.name(), position);
+    let mut queue = sql::get_prompts(challenge).await?;
+    let position = sql::insert_prompt_at(challenge, position, &prompt_data).await?;
+    push_queue_history(challenge, queue.clone());
+    queue.insert(position - 1, prompt_data);
+
+    send_queue_preview(ctx, challenge, &queue, position as i64, &format!("Successfully inserted entry at position {position}!")).await
+}
+
+/// Error if a prompt is already in the queue or has been used in a previous week. Prompts are compared
+/// ignoring case and surrounding whitespace. `skip_position` excludes an entry of the queue from the
+/// check, e.g. the entry that is being edited.
+async fn check_duplicate_prompt(challenge: Challenge, prompt_string: &str, skip_position: Option<usize>) -> Res {
+    let normalise = |s: &str| s.trim().to_lowercase();
+    let prompt = normalise(prompt_string);
 
-    // The next operation reads the same database we just updated. Unfortunately it seems like we have to wait 
-    // just a bit in order to make sure that we get the correctly updated data.
-    let mut timer = tokio::time::interval(time::Duration::from_secs(1));
-    timer.tick().await;
-    timer.tick().await;
+    let queue = sql::get_prompts(challenge).await?;
+    if let Some(idx) = queue.iter().enumerate()
+        .position(|(idx, p)| Some(idx + 1) != skip_position && normalise(&p.prompt_string) == prompt) {
+        return Err(format!("‘{}’ is already in the queue at position {}. Set `force` to add it anyway.", prompt_string.trim(), idx + 1).into());
+    }
+
+    if let Some((week_num, _)) = sql::get_used_prompts(challenge).await?.into_iter().find(|(_, p)| normalise(p) == prompt) {
+        return Err(format!("‘{}’ was already used in week {}. Set `force` to add it anyway.", prompt_string.trim(), week_num).into());
+    }
+
+    Ok(())
+}
 
-    let (week_num, start_time, end_time) = forecast_prompt_details(challenge, -1).await?;
-    
-    // Generate image based on new prompt.
+/// Reply with the announcement image for an entry of a queue, using a copy of the
+/// queue that the caller has already brought up to date.
+async fn send_queue_preview(ctx: Context<'_>, challenge: Challenge, queue: &[PromptData], position: i64, content: &str) -> Res {
+    let (week_num, start_time, end_time) = forecast_queue_details(challenge, queue, position).await?;
+    let index = if position < 0 { queue.len() as i64 + position } else { position - 1 };
+    let prompt_data = queue.get(index as usize).ok_or("No prompt found at given position.")?;
+
+    // Generate image based on the prompt.
     ctx.defer_ephemeral().await?;
-    let path = generate_challenge_image(challenge, week_num, Announcement { prompt_string: prompt_data.prompt_string, size_percentage: prompt_data.size_percentage.unwrap_or(100) },
-        start_time, end_time, false).await?;
+    let path = generate_challenge_image(challenge, week_num, Announcement { prompt_string: prompt_data.prompt_string.clone(),
+        size_percentage: prompt_data.size_percentage.unwrap_or(100) }, start_time, end_time, false).await?;
 
     // Get mtime. This is just a little sanity check.
     file_mtime(&path)?;
 
     // Reply with the image.
     ctx.send(CreateReply::default()
-        .content("Successfully added entry!")
-        .attachment(CreateAttachment::path(path).await?)
+        .content(content)
+        .attachment(take_challenge_image(&path).await?)
     ).await?;
     Ok(())
 }
 
 /// Edit an existing entry of a given queue.
 #[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "edit", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
 pub async fn queue_edit(
     ctx: Context<'_>,
     #[description = "Which challenge to edit a prompt for"] challenge: Challenge,
     #[description = "Position in the queue of the prompt to edit"] position: usize,
+    #[description = "New text of the prompt"] prompt_string: Option<String>,
     #[description = "New size modifier of the prompt"] size_percentage: Option<u16>,
     #[description = "New duration of the challenge in weeks"] custom_duration: Option<u16>,
     #[description = "Whether or not the week should be special"] is_special: Option<bool>,
-    #[description = "Any extra text to accompany the announcement of this glyph"] extra_announcement_text: Option<String>
+    #[description = "Any extra text to accompany the announcement of this glyph"] extra_announcement_text: Option<String>,
+    #[description = "Don't start this prompt before this date (YYYY-MM-DD, UTC), or `none` to remove it"] fixed_start_date: Option<String>,
+    #[description = "Change the text even if it is already queued or has been used before - defaults to false"] force: Option<bool>
 ) -> Res {
     let (id, mut prompt_data) = get_prompt_id_data(challenge, position).await?;
     // whether or not this operation necessitates showing the user the new image because it has changed
     let mut changed = false;
-    if let Some(v) = size_percentage { if v == 0 { return Err("Cannot set size_percentage to 0.".into()) } else {
-        prompt_data.size_percentage = size_percentage.filter(|x| x != &100); changed = true; } }
-    if let Some(v) = custom_duration { if v == 0 { return Err("Cannot set custom_duration to 0.".into()) } else {
-        prompt_data.custom_duration = custom_duration; changed = true; } }
+    if let Some(v) = prompt_string {
+        let v = normalise_prompt_string(&v)?;
+        if !force.unwrap_or(false) { check_duplicate_prompt(challenge, &v, Some(position)).await?; }
+        prompt_data.prompt_string = v; changed = true;
+    }
+    if let Some(_) = size_percentage { prompt_data.size_percentage = size_percentage.filter(|x| x != &100); changed = true; }
+    if let Some(_) = custom_duration { prompt_data.custom_duration = custom_duration; changed = true; }
     if let Some(_) = is_special { prompt_data.is_special = is_special.filter(|x| x == &true); }
     if let Some(_) = &extra_announcement_text { prompt_data.extra_announcement_text = extra_announcement_text; }
+    if let Some(v) = fixed_start_date {
+        prompt_data.fixed_start_time = match v.trim() { "none" => None, date => Some(parse_start_date(date)?) }; changed = true;
+    }
+    prompt_data.validate()?;
 
     info!("Modifying prompt {}:{} to {:?} in db...", challenge.name(), position, prompt_data);
+    let mut queue = sql::get_prompts(challenge).await?;
     let successful = edit_prompt(id, &prompt_data).await?;
 
     if !successful {
         ctx.say("Database operation failed while modifying prompt.").await?;
         return Ok(())
     }
+    push_queue_history(challenge, queue.clone());
+    invalidate_queue_images(challenge).await;
 
     if changed {
-
-        // The next operation reads the same database we just updated. Unfortunately it seems like we have to wait 
-        // just a bit in order to make sure that we get the correctly updated data.
-        let mut timer = tokio::time::interval(time::Duration::from_secs(1));
-        timer.tick().await;
-        timer.tick().await;
-        let (week_num, start_time, end_time) = forecast_prompt_details(challenge, position as i64).await?;
-        
-        // Generate image based on modified prompt.
-        ctx.defer_ephemeral().await?;
-        let path = generate_challenge_image(challenge, week_num, Announcement { prompt_string: prompt_data.prompt_string, 
-            size_percentage: prompt_data.size_percentage.unwrap_or(100) },
-        start_time, end_time, false).await?;
-
-        // Get mtime. This is just a little sanity check.
-        file_mtime(&path)?;
-
-        // Reply with the image.
-        ctx.send(CreateReply::default()
-            .content("Successfully modified entry!")
-            .attachment(CreateAttachment::path(path).await?)
-        ).await?;
+        // Forecast using the data we just wrote rather than reading it back.
+        if let Some(entry) = queue.get_mut(position - 1) { *entry = prompt_data.clone(); }
+        send_queue_preview(ctx, challenge, &queue, position as i64, "Successfully modified entry!").await?;
     }
     else {
         ctx.send(CreateReply::default()
@@ -218,6 +383,7 @@ pub async fn queue_edit(
 
 /// Swap two existing entries of a given queue.
 #[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "swap", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
 pub async fn queue_swap(
     ctx: Context<'_>,
     #[description = "Which challenge to swap two prompts for"] challenge: Challenge,
@@ -231,84 +397,430 @@ pub async fn queue_swap(
     }
 
     info!("Swapping prompts {}:{} and {}:{} in db...", challenge.name(), position1, challenge.name(), position2);
+    let queue = sql::get_prompts(challenge).await?;
     let successful = swap_prompts(challenge, position1, position2).await?;
 
     if !successful { ctx.say("Something went wrong in the database while swapping.").await?; }
-    else { ctx.say("Successfully swapped prompts!").await?; }
+    else {
+        push_queue_history(challenge, queue);
+        ctx.say("Successfully swapped prompts!").await?;
+    }
     Ok(())
 }
 
 /// Move an entry of a queue into a specified position.
 #[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "move", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
 pub async fn queue_move(
     ctx: Context<'_>,
     #[description = "Which challenge to move a prompt for"] challenge: Challenge,
     #[description = "Position of prompt to move"] from: usize,
     #[description = "Position to move into"] to: usize,
 ) -> Res {
+    if from == to {
+        ctx.say("Trying to move prompt into the same position it's already in.").await?;
+        return Ok(());
+    }
+
     info!("Moving prompt {}:{} into {}:{} in db...", challenge.name(), from, challenge.name(), to);
-    let mut successful = true; 
-
-    match from.cmp(&to) {
-    std::cmp::Ordering::Equal => { ctx.say("Trying to move prompt into the same position it's already in.").await?; return Ok(());},
-        std::cmp::Ordering::Greater => { for n in (to+1)..=from {
-            successful &= swap_prompts(challenge, to, n).await?;
-        }},
-        std::cmp::Ordering::Less => { for n in ((from+1)..=to).rev() {
-            successful &= swap_prompts(challenge, from, n).await?;
-        }}, 
-    }
-    
+    let queue = sql::get_prompts(challenge).await?;
+    let successful = move_prompt(challenge, from, to).await?;
+
     if !successful { ctx.say("Database operation failed while moving prompt.").await?; }
-    else { ctx.say("Successfully moved prompt!").await?; }
+    else {
+        push_queue_history(challenge, queue);
+        ctx.say("Successfully moved prompt!").await?;
+    }
+    Ok(())
+}
+
+/// Put the entries of a queue into a random order.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "shuffle", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn queue_shuffle(
+    ctx: Context<'_>,
+    #[description = "Which challenge to shuffle the queue for"] challenge: Challenge,
+    #[description = "Seed for the shuffle, to make it reproducible - defaults to a random one"] seed: Option<u64>,
+) -> Res {
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    info!("Shuffling queue {} in db (seed: {:?})...", challenge.name(), seed);
+    let queue = sql::get_prompts(challenge).await?;
+    if !shuffle_prompts(challenge, &mut rng).await? {
+        ctx.say("Database operation failed while shuffling queue.").await?;
+        return Ok(());
+    }
+    push_queue_history(challenge, queue);
+
+    let order = sql::get_prompts(challenge).await?.iter().enumerate()
+        .map(|(idx, prompt)| format!("**{}**: {}", idx + 1, prompt.prompt_string))
+        .collect::<Vec<_>>().join("\n");
+    ctx.say(safe_truncate(format!("Shuffled queue {}. New order:\n{}", challenge.name(), order), 2000)).await?;
+    Ok(())
+}
+
+/// Toggle whether an entry of a queue is skipped. Skipped prompts stay in the queue, but
+/// the scheduler passes over them when starting a new week.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "skip", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn queue_skip(
+    ctx: Context<'_>,
+    #[description = "Which challenge to skip a prompt for"] challenge: Challenge,
+    #[description = "Position in the queue of the prompt to skip or unskip"] position: usize,
+) -> Res {
+    let (id, mut prompt_data) = get_prompt_id_data(challenge, position).await?;
+    prompt_data.skipped = !prompt_data.skipped;
+
+    info!("Setting skipped of prompt {}:{} to {} in db...", challenge.name(), position, prompt_data.skipped);
+    let queue = sql::get_prompts(challenge).await?;
+    if !edit_prompt(id, &prompt_data).await? {
+        ctx.say("Database operation failed while modifying prompt.").await?;
+        return Ok(());
+    }
+    push_queue_history(challenge, queue);
+
+    ctx.say(match prompt_data.skipped {
+        true => format!("‘{}’ will be skipped until it is unskipped.", prompt_data.prompt_string),
+        false => format!("‘{}’ is no longer skipped.", prompt_data.prompt_string),
+    }).await?;
     Ok(())
 }
 
 /// Show the current queue for a challenge.
 #[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "list", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
 pub async fn queue_list(
     ctx: Context<'_>,
     #[description = "Which challenge to show the queue for"] challenge: Challenge,
 ) -> Res {
+    const PAGE_SIZE: usize = 10;
+
     // Get the queue.
     let queue = sql::get_prompts(challenge).await?;
+    let num_pages = queue.len().div_ceil(PAGE_SIZE).max(1);
 
-    // Create embed.
-    let mut embed = create_embed(&ctx)
-        .author(CreateEmbedAuthor::new(format!("Queue for {} Challenge", challenge.name())))
-        .description("Listed properties: size_percentage, custom_duration, is_special, extra_announcement_text.\nIf a property has its default value, it is not listed.");
-    for (idx, prompt) in queue.into_iter().enumerate() {
-        embed = embed.field(format!("**{}**: {}", idx + 1, prompt.prompt_string),[
-            prompt.size_percentage.map(|x| format!("> size_percentage: {x}%")),
-            prompt.custom_duration.map(|x| format!("> custom_duration: {x} weeks")),
-            prompt.is_special.map(|x| format!("> is_special: {x}")),
-            prompt.extra_announcement_text.map(|x| format!("> extra_announcement_text: {x}"))
-        ].into_iter().flatten().collect::<Vec<String>>().join("\n"), false);
-    }
+    // Create the embed for one page. Fields are truncated so that a full page
+    // stays within Discord's limit on the total size of an embed.
+    let page_embed = |page: usize| {
+        let title = match num_pages {
+            1 => format!("Queue for {} Challenge", challenge.name()),
+            _ => format!("Queue for {} Challenge (page {} of {})", challenge.name(), page + 1, num_pages),
+        };
+        let mut embed = create_challenge_embed(&ctx, challenge)
+            .author(CreateEmbedAuthor::new(title))
+            .description("Listed properties: size_percentage, custom_duration, is_special, extra_announcement_text, fixed_start_time.\nIf a property has its default value, it is not listed. Skipped prompts are struck through.");
+        for (idx, prompt) in queue.iter().enumerate().skip(page * PAGE_SIZE).take(PAGE_SIZE) {
+            let name = match prompt.skipped {
+                true => format!("~~**{}**: {}~~ (skipped)", idx + 1, prompt.prompt_string),
+                false => format!("**{}**: {}", idx + 1, prompt.prompt_string),
+            };
+            embed = embed.field(safe_truncate(name, 200), safe_truncate([
+                prompt.size_percentage.map(|x| format!("> size_percentage: {x}%")),
+                prompt.custom_duration.map(|x| format!("> custom_duration: {x} weeks")),
+                prompt.is_special.map(|x| format!("> is_special: {x}")),
+                prompt.extra_announcement_text.as_ref().map(|x| format!("> extra_announcement_text: {x}")),
+                prompt.fixed_start_time.map(|x| format!("> fixed_start_time: <t:{x}:D>"))
+            ].into_iter().flatten().collect::<Vec<String>>().join("\n"), 300), false);
+        }
+        embed
+    };
 
     // Send it.
-    ctx.send(CreateReply::default().embed(embed)).await?;
+    send_paginated(ctx, num_pages, page_embed).await
+}
+
+/// Show when each prompt in a queue is expected to run.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "schedule", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn queue_schedule(
+    ctx: Context<'_>,
+    #[description = "Which challenge to show the schedule for"] challenge: Challenge,
+) -> Res {
+    const PAGE_SIZE: usize = 20;
+
+    let queue = sql::get_prompts(challenge).await?;
+    if queue.is_empty() {
+        ctx.say(format!("Queue {} is empty, so nothing is scheduled.", challenge.name())).await?;
+        return Ok(());
+    }
+    let forecast = forecast_queue(challenge, &queue).await?;
+    let num_pages = queue.len().div_ceil(PAGE_SIZE);
+
+    let page_embed = |page: usize| {
+        let title = match num_pages {
+            1 => format!("Schedule for {} Challenge", challenge.name()),
+            _ => format!("Schedule for {} Challenge (page {} of {})", challenge.name(), page + 1, num_pages),
+        };
+        let lines: Vec<String> = queue.iter().zip(&forecast).enumerate().skip(page * PAGE_SIZE).take(PAGE_SIZE)
+            .map(|(idx, (prompt, (week_num, start_time, end_time)))| match prompt.skipped {
+                true => format!("~~**{}**: ‘{}’~~ – skipped", idx + 1, prompt.prompt_string),
+                false => format!("**{}**: week {} – ‘{}’, {} to {}",
+                    idx + 1, week_num, prompt.prompt_string, start_time.to_discord('d'), end_time.to_discord('d')),
+            })
+            .collect();
+        create_challenge_embed(&ctx, challenge)
+            .author(CreateEmbedAuthor::new(title))
+            .description(lines.join("\n"))
+    };
+
+    send_paginated(ctx, num_pages, page_embed).await
+}
+
+/// Show when an entry of a queue is expected to be announced, polled, and concluded.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "when", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn queue_when(
+    ctx: Context<'_>,
+    #[description = "Which challenge to forecast a prompt for"] challenge: Challenge,
+    #[description = "Position of the prompt in the queue - negative positions count from the end"] position: i64,
+) -> Res {
+    let queue = sql::get_prompts(challenge).await?;
+    let forecast = forecast_queue(challenge, &queue).await?;
+    let idx = if position < 0 { queue.len() as i64 + position } else { position - 1 };
+    let Some(idx) = usize::try_from(idx).ok().filter(|i| *i < queue.len()) else {
+        return Err(format!("There is no prompt at position {position} in challenge {}.", challenge.name()).into());
+    };
+    let prompt = &queue[idx];
+    let (week_num, start_time, end_time) = forecast[idx];
+
+    // The poll is posted when the week ends if voting has its own window, and otherwise when the
+    // next week starts, with voting running until that week ends. If the queue has nothing after
+    // this prompt, assume that the next week is a regular one.
+    let next = queue.iter().zip(&forecast).skip(idx + 1).find(|(p, _)| !p.skipped).map(|(_, f)| *f);
+    let (poll_time, results_time) = match config().voting_duration {
+        Some(duration) => (end_time, end_time + duration),
+        None => match next {
+            Some((_, next_start_time, next_end_time)) => (next_start_time, next_end_time),
+            None => (end_time + config().time_gap, end_time + challenge.default_duration()),
+        },
+    };
+
+    let when = |t: Timestamp| format!("{} ({})", t.to_discord('f'), t.to_discord('R'));
+    let mut lines = vec![
+        format!("‘{}’ is expected to be week {} of the {} challenge.", prompt.prompt_string, week_num, challenge.name()),
+        format!("**Announcement:** {}", when(start_time)),
+    ];
+    if prompt.is_special.unwrap_or(false) {
+        lines.push("This week is special, so there is no poll.".to_owned());
+    } else {
+        lines.push(format!("**Poll:** {}", when(poll_time)));
+        lines.push(format!("**Results:** {}", when(results_time)));
+    }
+    if prompt.skipped {
+        lines.push("This prompt is currently skipped; these are the dates it would get if it weren't.".to_owned());
+    }
+    ctx.say(lines.join("\n")).await?;
     Ok(())
 }
 
-/// Remove an entry from a queue.
+/// Reply with the first of several pages of embeds, with buttons to move between the
+/// pages if there is more than one. `page_embed` creates the embed for a given page.
+async fn send_paginated(ctx: Context<'_>, num_pages: usize, page_embed: impl Fn(usize) -> CreateEmbed) -> Res {
+    const PAGE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(300);
+
+    let ctx_id = ctx.id();
+    let prev_id = format!("{ctx_id}-prev");
+    let next_id = format!("{ctx_id}-next");
+    let page_reply = |page: usize| {
+        let reply = CreateReply::default().embed(page_embed(page));
+        if num_pages == 1 { return reply; }
+        reply.components(vec![CreateActionRow::Buttons(vec![
+            CreateButton::new(&prev_id).style(ButtonStyle::Secondary).label("◀").disabled(page == 0),
+            CreateButton::new(&next_id).style(ButtonStyle::Secondary).label("▶").disabled(page + 1 == num_pages),
+        ])])
+    };
+
+    let mut page = 0;
+    let reply = ctx.send(page_reply(page)).await?;
+    if num_pages == 1 { return Ok(()); }
+    while let Some(press) = ComponentInteractionCollector::new(ctx)
+        .author_id(ctx.author().id)
+        .filter(move |press| press.data.custom_id.starts_with(&format!("{ctx_id}-")))
+        .timeout(PAGE_TIMEOUT)
+        .await
+    {
+        if press.data.custom_id == next_id { page = (page + 1).min(num_pages - 1); }
+        else if press.data.custom_id == prev_id { page = page.saturating_sub(1); }
+        press.create_response(ctx, CreateInteractionResponse::Acknowledge).await?;
+        reply.edit(ctx, page_reply(page)).await?;
+    }
+
+    // Remove the buttons once they've timed out.
+    reply.edit(ctx, CreateReply::default().embed(page_embed(page)).components(vec![])).await?;
+    Ok(())
+}
+
+/// Remove an entry, or a range of entries, from a queue.
 #[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "remove", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
 pub async fn queue_remove(
     ctx: Context<'_>,
     #[description = "The challenge to remove an entry from"] challenge: Challenge,
     #[description = "The entry number in the queue to remove"] position: usize,
+    #[description = "Also remove every entry after it up to and including this one"] to: Option<usize>,
 ) -> Res {
+    let name = challenge.name();
+    let queue = sql::get_prompts(challenge).await?;
+    if let Some(to) = to {
+        info!("Removing prompts {}:{} to {}:{} from db...", name, position, name, to);
+        let removed = sql::delete_prompts(challenge, position, to).await?;
+        push_queue_history(challenge, queue);
+        invalidate_queue_images(challenge).await;
+        ctx.say(format!("Removed {removed} entries ({position} to {to}) from queue {name}.")).await?;
+        return Ok(());
+    }
+
     // Remove it.
     let changed = sql::delete_prompt(challenge, position).await?;
-    let name = challenge.name();
     // Send a reply.
-    if changed { ctx.say(format!("Removed entry {position} from queue {name}.")).await?; } //
+    if changed {
+        push_queue_history(challenge, queue);
+        invalidate_queue_images(challenge).await;
+        ctx.say(format!("Removed entry {position} from queue {name}.")).await?;
+    }
     else { ctx.say("No such entry").await?; }
     Ok(())
 }
 
+/// Remove every entry from a queue.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "clear", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn queue_clear(
+    ctx: Context<'_>,
+    #[description = "The challenge whose queue to clear"] challenge: Challenge,
+) -> Res {
+    let name = challenge.name();
+    let queue = sql::get_prompts(challenge).await?;
+    let len = queue.len();
+    if len == 0 { ctx.say(format!("Queue {name} is already empty.")).await?; return Ok(()); }
+    if !confirm(ctx, &format!("This will remove all {len} entries from queue {name}. Are you sure?")).await? {
+        return Ok(());
+    }
+
+    info!("Clearing queue {}...", name);
+    let removed = sql::clear_prompts(challenge).await?;
+    push_queue_history(challenge, queue);
+    invalidate_queue_images(challenge).await;
+    ctx.say(format!("Removed {removed} entries from queue {name}.")).await?;
+    Ok(())
+}
+
+/// Ask the user to confirm an action using a pair of buttons. Returns whether they confirmed
+/// it before the prompt timed out.
+async fn confirm(ctx: Context<'_>, prompt: &str) -> ResT<bool> {
+    const CONFIRM_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+    // Prefix the ids with the id of this invocation, so we only pick up our own buttons.
+    let ctx_id = ctx.id();
+    let confirm_id = format!("{ctx_id}-confirm");
+    let cancel_id = format!("{ctx_id}-cancel");
+    let reply = ctx.send(CreateReply::default()
+        .content(prompt)
+        .components(vec![CreateActionRow::Buttons(vec![
+            CreateButton::new(&confirm_id).style(ButtonStyle::Danger).label("Confirm"),
+            CreateButton::new(&cancel_id).style(ButtonStyle::Secondary).label("Cancel"),
+        ])])
+    ).await?;
+
+    let press = ComponentInteractionCollector::new(ctx)
+        .author_id(ctx.author().id)
+        .filter(move |press| press.data.custom_id.starts_with(&format!("{ctx_id}-")))
+        .timeout(CONFIRM_TIMEOUT)
+        .await;
+    let (confirmed, outcome) = match &press {
+        Some(press) if press.data.custom_id == confirm_id => (true, "Confirmed."),
+        Some(_) => (false, "Cancelled."),
+        None => (false, "Timed out; nothing was changed."),
+    };
+    if let Some(press) = press {
+        press.create_response(ctx, CreateInteractionResponse::Acknowledge).await?;
+    }
+
+    // Remove the buttons so they can't be pressed again.
+    reply.edit(ctx, CreateReply::default()
+        .content(format!("{prompt}\n{outcome}"))
+        .components(vec![])
+    ).await?;
+    Ok(confirmed)
+}
+
+/// Export a queue as a JSON file.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "export", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn queue_export(
+    ctx: Context<'_>,
+    #[description = "The challenge whose queue to export"] challenge: Challenge,
+) -> Res {
+    let queue = sql::get_prompts(challenge).await?;
+    let json = serde_json::to_string_pretty(&queue)?;
+    ctx.send(CreateReply::default()
+        .content(format!("Exported {} entries.", queue.len()))
+        .attachment(CreateAttachment::bytes(json.into_bytes(), format!("{}_queue.json", challenge.short_name())))
+    ).await?;
+    Ok(())
+}
+
+/// Import entries into a queue from a JSON file, as produced by `/queue export`.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "import", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn queue_import(
+    ctx: Context<'_>,
+    #[description = "The challenge whose queue to import into"] challenge: Challenge,
+    #[description = "The JSON file to import"] file: Attachment,
+    #[description = "Whether to append to or replace the existing queue - defaults to append"] mode: Option<ImportMode>,
+) -> Res {
+    let prompts: Vec<PromptData> = serde_json::from_slice(&file.download().await?)
+        .map_err(|e| format!("Invalid queue file: {}", e))?;
+
+    // Validate everything before touching the database.
+    for (idx, prompt_data) in prompts.iter().enumerate() {
+        if prompt_data.challenge != challenge {
+            return Err(format!("Entry {} belongs to the {} challenge.", idx + 1, prompt_data.challenge.name()).into());
+        }
+        prompt_data.validate().map_err(|e| format!("Entry {}: {}", idx + 1, e))?;
+    }
+
+    let replace = matches!(mode, Some(ImportMode::Replace));
+    info!("Importing {} prompts into {} (replace: {})...", prompts.len(), challenge.name(), replace);
+    let queue = sql::get_prompts(challenge).await?;
+    sql::import_prompts(challenge, &prompts, replace).await?;
+    push_queue_history(challenge, queue);
+    invalidate_queue_images(challenge).await;
+    ctx.say(format!("Imported {} entries into queue {}.", prompts.len(), challenge.name())).await?;
+    Ok(())
+}
+
+/// Undo the last change made to a queue with one of the other queue commands.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "undo", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn queue_undo(
+    ctx: Context<'_>,
+    #[description = "The challenge whose queue to restore"] challenge: Challenge,
+) -> Res {
+    let name = challenge.name();
+    let Some(previous) = pop_queue_history(challenge) else {
+        ctx.say(format!("There are no changes to queue {name} to undo.")).await?;
+        return Ok(());
+    };
+
+    // Replacing the queue happens in a single transaction, so either the whole
+    // previous state is restored or nothing changes.
+    info!("Restoring queue {} to {} entries...", name, previous.len());
+    if let Err(e) = sql::import_prompts(challenge, &previous, true).await {
+        push_queue_history(challenge, previous);
+        return Err(e);
+    }
+    invalidate_queue_images(challenge).await;
+    ctx.say(format!("Restored queue {name} to its previous state ({} entries).", previous.len())).await?;
+    Ok(())
+}
+
 /// Preview an entry in the queue.
 #[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "preview", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
 pub async fn queue_preview(
     ctx: Context<'_>,
     #[description = "The challenge to preview an entry from"] challenge: Challenge,
@@ -322,13 +834,593 @@ pub async fn queue_preview(
         size_percentage: prompt_data.size_percentage.unwrap_or(100) }, start_time, end_time, false).await?;
 
     ctx.send(CreateReply::default()
-        .attachment(CreateAttachment::path(path).await?)
+        .attachment(take_challenge_image(&path).await?)
+    ).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error",
+ subcommands("admin_status", "admin_rollover", "admin_announce_now", "admin_repost_winners", "admin_extend", "admin_maintenance", "admin_backup", "admin_clear_votes", "admin_export_users", "admin_prune_images", "admin_resync_files", "admin_selftest", "admin_set_votes", "admin_show_votes", "admin_vote_audit"),
+ default_member_permissions = "ADMINISTRATOR")]
+pub async fn admin(_ctx: Context<'_>) -> Res { unreachable!(); }
+
+/// Check whether the bot is working as it should.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "status", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn admin_status(ctx: Context<'_>) -> Res {
+    ctx.defer_ephemeral().await?;
+    let describe = |res: Res| match res {
+        Ok(()) => "OK".to_owned(),
+        Err(e) => format!("failing ({})", e),
+    };
+
+    let mut lines = Vec::new();
+    for challenge in Challenge::all() {
+        let week = match get_current_week_num(challenge).await {
+            Ok(week_num) => week_num.to_string(),
+            Err(e) => format!("unknown ({})", e),
+        };
+        let queue = match sql::get_prompts(challenge).await {
+            Ok(queue) => queue.len().to_string(),
+            Err(e) => format!("unknown ({})", e),
+        };
+        lines.push(format!("**{}:** week {}, {} prompts queued", challenge.name(), week, queue));
+    }
+    lines.push(format!("**Database:** {}", describe(sql::ping_db().await)));
+    lines.push(format!("**Maintenance mode:** {}", if maintenance_mode() { "on" } else { "off" }));
+    let last_run = last_scheduler_run();
+    lines.push(format!("**Last scheduler run:** {}", if last_run == NULL_TIMESTAMP { "never".to_owned() }
+        else { format!("{} ({})", last_run.to_discord('f'), last_run.to_discord('R')) }));
+    lines.push(format!("**generate.py:** {}", describe(check_generation_script().await)));
+
+    ctx.send(CreateReply::default().embed(create_embed(&ctx)
+        .author(CreateEmbedAuthor::new("Status"))
+        .description(lines.join("\n"))
+    )).await?;
+    Ok(())
+}
+
+/// Perform whatever week transition the scheduler would perform next for a challenge.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "rollover", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn admin_rollover(
+    ctx: Context<'_>,
+    #[description = "The challenge to advance"] challenge: Challenge,
+    #[description = "Only report what would happen, without doing it - defaults to false"] dry_run: Option<bool>
+) -> Res {
+    let dry_run = dry_run.unwrap_or(false);
+    info!("Manually advancing {} challenge{}...", challenge.short_name(), if dry_run { " (dry run)" } else { "" });
+    let outcome = advance_challenge(ctx.serenity_context(), challenge, dry_run).await?;
+    ctx.say(outcome).await?;
+    Ok(())
+}
+
+/// Start the next week of a challenge now, ending the current one early if necessary.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "announce_now", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn admin_announce_now(
+    ctx: Context<'_>,
+    #[description = "The challenge to start the next week of"] challenge: Challenge,
+) -> Res {
+    let current_week_num = get_current_week_num(challenge).await?;
+    if !confirm(ctx, &format!("This will end week {} of the {} challenge if it is still running and start week {} right away. Are you sure?",
+        current_week_num, challenge.name(), current_week_num + 1)).await? {
+        return Ok(());
+    }
+
+    info!("Announcing next {} challenge early...", challenge.short_name());
+    let outcome = announce_now(ctx.serenity_context(), challenge).await?;
+    ctx.say(outcome).await?;
+    Ok(())
+}
+
+/// Give the current week of a challenge more time, reopening it if it has already ended.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "extend", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn admin_extend(
+    ctx: Context<'_>,
+    #[description = "The challenge whose current week to extend"] challenge: Challenge,
+    #[description = "How many days to move the deadline by - negative values bring it forward"] days: i64,
+) -> Res {
+    if days.abs() > 365 { return Err("Weeks can be extended by at most a year at a time.".into()); }
+    info!("Extending current {} week by {} days...", challenge.short_name(), days);
+    let end_time = extend_week(challenge, Duration::days(days)).await?;
+    ctx.say(format!("The current week of the {} challenge now ends {} ({}).",
+        challenge.name(), end_time.to_discord('F'), end_time.to_discord('R'))).await?;
+    Ok(())
+}
+
+/// Pause or resume submissions and the scheduler, e.g. while something is being fixed.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "maintenance", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn admin_maintenance(
+    ctx: Context<'_>,
+    #[description = "Whether maintenance mode should be on"] enabled: bool,
+) -> Res {
+    set_maintenance_mode(enabled).await?;
+    ctx.say(if enabled { "Maintenance mode is on. Submissions and the scheduler are paused until it is turned off." }
+        else { "Maintenance mode is off." }).await?;
+    Ok(())
+}
+
+/// Generate the winner images of a week again and post them to the announcements channel, e.g. because
+/// the ones posted before were wrong. This doesn't change anyone's profile.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "repost_winners", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn admin_repost_winners(
+    ctx: Context<'_>,
+    #[description = "The challenge to post the winners of"] challenge: Challenge,
+    #[description = "The week whose winners to post"] week: i64,
+) -> Res {
+    ctx.defer_ephemeral().await?;
+    if !voting_concluded(challenge, week).await? {
+        return Err(format!("Voting for week {} has not concluded yet.", week).into());
+    }
+
+    // Generate every image before posting any, so a failure doesn't leave a partial set behind.
+    let placed = rank_winners(tally_week(challenge, week).await?).len();
+    let mut paths = Vec::new();
+    for position in [WinnerPosition::First, WinnerPosition::Second, WinnerPosition::Third].into_iter().take(placed) {
+        match generate_week_winner_image(ctx, challenge, week, position, false).await {
+            Ok(path) => paths.push(path),
+            Err(e) => {
+                for path in paths { let _ = take_challenge_image(&path).await; }
+                return Err(e);
+            }
+        }
+    }
+    if paths.is_empty() {
+        return Err(format!("Nobody placed in week {} of the {} challenge.", week, challenge.name()).into());
+    }
+
+    let mut links = Vec::new();
+    for path in paths {
+        let message = challenge.announcement_channel().send_message(&ctx, CreateMessage::new()
+            .add_file(take_challenge_image(&path).await?)
+        ).await?;
+        info!("Reposted winner image {} for week {} of {}", message.id, week, challenge.short_name());
+        links.push(message_link(config().server_id, message.channel_id, message.id));
+    }
+    ctx.say(format!("Posted the winners of week {}:\n{}", week, links.join("\n"))).await?;
+    Ok(())
+}
+
+/// Make a backup of the database and send it as an attachment.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "backup", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn admin_backup(ctx: Context<'_>) -> Res {
+    ctx.defer_ephemeral().await?;
+    let path = sql::backup_db().await?;
+    info!("Backed up database to {}", path);
+    ctx.send(CreateReply::default()
+        .content(format!("Backed up database to `{}`.", path))
+        .attachment(CreateAttachment::path(&path).await?)
+    ).await?;
+    Ok(())
+}
+
+/// Export every user’s profile as a CSV file.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "export_users", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn admin_export_users(
+    ctx: Context<'_>,
+    #[description = "Include user ids and nicknames rather than anonymising users - defaults to false"] include_ids: Option<bool>,
+) -> Res {
+    ctx.defer_ephemeral().await?;
+    let path = sql::export_users(include_ids.unwrap_or(false)).await?;
+    info!("Exported users to {}", path);
+    ctx.send(CreateReply::default()
+        .content(format!("Exported users to `{}`.", path))
+        .attachment(CreateAttachment::path(&path).await?)
+    ).await?;
+    Ok(())
+}
+
+/// Delete the submission images of old weeks.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "prune_images", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn admin_prune_images(
+    ctx: Context<'_>,
+    #[description = "Keep the images of this many weeks before the current one"] older_than_weeks: u32,
+    #[description = "Only report what would be deleted, without deleting it - defaults to false"] dry_run: Option<bool>
+) -> Res {
+    ctx.defer_ephemeral().await?;
+    let dry_run = dry_run.unwrap_or(false);
+    let mut lines = Vec::new();
+    for challenge in Challenge::all() {
+        // the cutoff is never after the current week, so neither it nor the pending week is touched
+        let cutoff_week = get_current_week_num(challenge).await? - older_than_weeks as i64;
+        let (files, dirs) = prune_submission_images(challenge, cutoff_week, dry_run).await?;
+        info!("Pruned {} files and {} directories of {} challenge images before week {}{}", 
+            files, dirs, challenge.short_name(), cutoff_week, if dry_run { " (dry run)" } else { "" });
+        lines.push(format!("{}: {} {} files and {} directories from before week {}.", challenge.name(), 
+            if dry_run { "would remove" } else { "removed" }, files, dirs, cutoff_week));
+    }
+    ctx.say(lines.join("\n")).await?;
+    Ok(())
+}
+
+/// Download the images of a week's submissions again if they are missing from the file system.
+//
+// The links stored in the database expire, so this fetches each submission message
+// again to get a fresh link to its attachment.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "resync_files", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn admin_resync_files(
+    ctx: Context<'_>,
+    #[description = "The challenge of the submissions"] challenge: Challenge,
+    #[description = "The week of the submissions"] week: i64,
+) -> Res {
+    ctx.defer_ephemeral().await?;
+    initialise_submissions_directory(challenge, week).await?;
+    let (mut downloaded, mut present, mut failed) = (0, 0, 0);
+    for (_, message_id) in get_submissions(challenge, week).await? {
+        if submission_exists(message_id, challenge, week).await? { present += 1; continue; }
+        let result = async {
+            let message = challenge.submission_channel().message(&ctx, message_id).await?;
+            let att = message.attachments.first().ok_or("Submission has no attachment")?;
+            download_submission(att, message_id, challenge, week).await
+        }.await;
+        match result {
+            Ok(()) => downloaded += 1,
+            Err(e) => { err!("Skipping submission {} while resyncing files: {}", message_id, e); failed += 1; }
+        }
+    }
+
+    info!("Resynced files of {} week {}: {} downloaded, {} present, {} failed", challenge.short_name(), week, downloaded, present, failed);
+    ctx.say(format!("Downloaded {downloaded} missing files, {present} were already present, and {failed} could not be downloaded (see the log).")).await?;
+    Ok(())
+}
+
+/// Generate a sample announcement image to check that image generation works, without
+/// touching any challenge. The image is deleted again after it has been sent.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "selftest", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn admin_selftest(ctx: Context<'_>) -> Res {
+    ctx.defer_ephemeral().await?;
+    let start_time: DateTime<Utc> = DateTime::from_timestamp(1_704_067_200, 0).ok_or("Invalid self-test date")?; // 2024-01-01
+    let started = time::Instant::now();
+    let path = render_challenge_image(Challenge::Glyph, 1, Announcement { prompt_string: "A".to_owned(), size_percentage: 100 },
+        start_time.into(), (start_time + Duration::days(7)).into(), false).await?;
+
+    // Make sure something was actually written, since the script might exit successfully without output.
+    file_mtime(&path).map_err(|e| format!("Generated image {} is missing: {}", path, e))?;
+    let empty = std::fs::metadata(&path)?.len() == 0;
+    let attachment = take_challenge_image(&path).await?;
+    if empty { return Err(format!("Generated image {} is empty.", path).into()); }
+
+    ctx.send(CreateReply::default()
+        .content(format!("Image generation works (took {:.1}s).", started.elapsed().as_secs_f64()))
+        .attachment(attachment)
     ).await?;
     Ok(())
 }
 
+/// Set the stored vote total of a submission, e.g. to import historical results or correct a mistake.
+/// This bypasses the individual votes: polls, vote summaries and placements still count those.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "set_votes", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn admin_set_votes(
+    ctx: Context<'_>,
+    #[description = "The challenge of the submission"] challenge: Challenge,
+    #[description = "The week of the submission"] week: i64,
+    #[description = "The number of the submission in the poll, starting at 1"] submission: usize,
+    #[description = "The number of votes to record"] count: i64,
+) -> Res {
+    if count < 0 { return Err("The vote count must not be negative.".into()); }
+    // the totals are recomputed from the individual votes when voting concludes
+    if !voting_concluded(challenge, week).await? {
+        return Err(format!("Voting on week {} hasn't concluded yet, so its totals would be overwritten.", week).into());
+    }
+    let submissions = get_submissions(challenge, week).await?;
+    let (author, message) = submission.checked_sub(1).and_then(|idx| submissions.get(idx)).copied()
+        .ok_or(format!("Week {} has {} submissions, so there is no submission {}.", week, submissions.len(), submission))?;
+
+    info!("Setting votes of submission {} of {}:{} to {}", submission, challenge.name(), week, count);
+    if !sql::set_submission_votes(challenge, week, message, count).await? {
+        return Err("Database operation failed while setting the vote count.".into());
+    }
+    ctx.say(format!("Recorded {} vote{} for submission {} (by <@{}>) of week {}.", count, if count == 1 { "" } else { "s" }, submission, author, week)).await?;
+    Ok(())
+}
+
+/// Show which submissions a user voted for in a week.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "show_votes", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn admin_show_votes(
+    ctx: Context<'_>,
+    #[description = "The challenge of the poll"] challenge: Challenge,
+    #[description = "The week whose submissions were voted on"] week: i64,
+    #[description = "The user whose votes to show"] user: User,
+) -> Res {
+    let submissions = get_submissions(challenge, week).await?;
+    let votes = get_votes(challenge, week, user.id, submissions.len() as i64).await?;
+    if votes.is_empty() {
+        ctx.say(format!("<@{}> didn't vote in week {} of the {} challenge.", user.id, week, challenge.name())).await?;
+        return Ok(());
+    }
+
+    let lines = votes.iter().map(|idx| {
+        let (author, _) = submissions[*idx as usize];
+        format!(
+            "{} – submission {} by <@{}>",
+            VOTING_EMOJI_SEQUENCE.get(*idx as usize).map(|x| x.display_string()).unwrap_or_else(|| format!("#{}", idx + 1)),
+            idx + 1, author
+        )
+    }).collect::<Vec<_>>().join("\n");
+    let embed = create_challenge_embed(&ctx, challenge)
+        .author(CreateEmbedAuthor::new(format!("{} Challenge Week {} Votes", challenge.name(), week)))
+        .description(safe_truncate(format!("Votes by <@{}>:\n{}", user.id, lines), 4096));
+    ctx.send(CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+/// Remove all of a user's votes for a week, e.g. so that a banned user's votes aren't counted.
+/// This has to happen before voting on the week concludes, since the totals are stored then.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "clear_votes", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn admin_clear_votes(
+    ctx: Context<'_>,
+    #[description = "The challenge of the poll"] challenge: Challenge,
+    #[description = "The week whose submissions were voted on"] week: i64,
+    #[description = "The user whose votes to remove"] user: User,
+) -> Res {
+    if voting_concluded(challenge, week).await? {
+        return Err(format!("Voting on week {} has already concluded; use `/admin set_votes` to correct its totals instead.", week).into());
+    }
+
+    info!("Clearing votes of user {} for {}:{}", user.id, challenge.name(), week);
+    let removed = sql::clear_votes(challenge, week, user.id).await?;
+    ctx.say(format!("Removed {} vote{} by <@{}> from week {}.", removed, if removed == 1 { "" } else { "s" }, user.id, week)).await?;
+    Ok(())
+}
+
+/// Show how many submissions each user voted for in a week, to help spot ballot stuffing.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "vote_audit", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn admin_vote_audit(
+    ctx: Context<'_>,
+    #[description = "The challenge of the poll"] challenge: Challenge,
+    #[description = "The week whose submissions were voted on"] week: i64,
+) -> Res {
+    let week_info = get_week_info(week, challenge).await
+        .map_err(|_| format!("There is no week {} for the {} challenge.", week, challenge.name()))?;
+    let counts = sql::count_votes_per_user(challenge, week).await?;
+
+    let mut embed = create_challenge_embed(&ctx, challenge).author(CreateEmbedAuthor::new(format!("{} Challenge Week {} Vote Audit", challenge.name(), week)));
+    if counts.is_empty() {
+        embed = embed.description("Nobody voted in this poll.");
+        ctx.send(CreateReply::default().embed(embed)).await?;
+        return Ok(());
+    }
+
+    // Flag users who voted for both more than twice as many submissions as the median
+    // voter and more than half of all submissions.
+    let median = counts[counts.len() / 2].1;
+    let suspicious = |count: i64| count > 2 * median && 2 * count > week_info.num_subs;
+
+    // Number of users for each number of votes, in ascending order of votes.
+    let mut distribution: Vec<(i64, usize)> = Vec::new();
+    for (_, count) in counts.iter().rev() {
+        match distribution.last_mut() {
+            Some((c, users)) if *c == *count => *users += 1,
+            _ => distribution.push((*count, 1)),
+        }
+    }
+
+    let distribution = distribution.iter().map(|(count, users)| format!(
+        "{} vote{} – {} user{}",
+        count, if *count == 1 { "" } else { "s" },
+        users, if *users == 1 { "" } else { "s" },
+    )).collect::<Vec<_>>().join("\n");
+    let lines = counts.iter().map(|(user, count)| format!(
+        "<@{}> – {} vote{}{}",
+        user, count, if *count == 1 { "" } else { "s" },
+        if suspicious(*count) { " ⚠️" } else { "" },
+    )).collect::<Vec<_>>().join("\n");
+    let flagged = counts.iter().filter(|(_, count)| suspicious(*count)).count();
+
+    embed = embed
+        .description(safe_truncate(lines, 4096))
+        .field("Voters", counts.len().to_string(), true)
+        .field("Submissions", week_info.num_subs.to_string(), true)
+        .field("Flagged", flagged.to_string(), true)
+        .field("Distribution", safe_truncate(distribution, 1024), false);
+    ctx.send(CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error",
+ subcommands("preview_spiel"),
+ default_member_permissions = "ADMINISTRATOR")]
+pub async fn preview(_ctx: Context<'_>) -> Res { unreachable!(); }
+
+/// Show the raw text of the next announcement and poll, so it can be proofread.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "spiel", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn preview_spiel(
+    ctx: Context<'_>,
+    #[description = "The challenge to preview the text for"] challenge: Challenge,
+) -> Res {
+    // The next announcement is for the first prompt in the queue that isn't skipped. Unless voting
+    // has its own window, the poll that is posted alongside it uses the same timestamps.
+    let (position, _, prompt_data) = sql::get_next_prompt(challenge).await?;
+    let (week_num, _, end_time) = forecast_prompt_details(challenge, position as i64).await?;
+    let announcement = challenge.format_announcement_spiel(week_num, &prompt_data.prompt_string,
+        &end_time.to_discord('F'), &end_time.to_discord('R'));
+    let voting_end_time = match config().voting_duration {
+        Some(duration) => get_week_info(week_num - 1, challenge).await?.target_end_time + duration,
+        None => end_time,
+    };
+    let poll = format_poll_spiel(&voting_end_time.to_discord('F'), &voting_end_time.to_discord('R'));
+
+    let mut content = format!("Announcement for week {week_num}:\n```\n{announcement}\n```\nPoll for week {}:\n```\n{poll}\n```", week_num - 1);
+    if prompt_data.is_special.unwrap_or(false) {
+        content.push_str(&format!("\nWeek {week_num} is special, so its announcement will not be posted."));
+    }
+    ctx.say(safe_truncate(content, 2000)).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error",
+ subcommands("poll_tally"),
+ default_member_permissions = "ADMINISTRATOR")]
+pub async fn poll(_ctx: Context<'_>) -> Res { unreachable!(); }
+
+/// Show the current number of votes for each submission in a poll.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "tally", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn poll_tally(
+    ctx: Context<'_>,
+    #[description = "The challenge of the poll"] challenge: Challenge,
+    #[description = "The week whose submissions are being voted on - defaults to the poll that is currently open"] week: Option<i64>,
+) -> Res {
+    ctx.defer_ephemeral().await?;
+    // voting for a week happens during the week after it, unless it opened as soon as the week ended
+    let week_num = match week {
+        Some(w) => w,
+        None => {
+            let current_week_num = get_current_week_num(challenge).await?;
+            if get_week_info(current_week_num, challenge).await?.voting_open() { current_week_num } else { current_week_num - 1 }
+        }
+    };
+    let week_info = get_week_info(week_num, challenge).await
+        .map_err(|_| format!("There is no week {} for the {} challenge.", week_num, challenge.name()))?;
+
+    // only count votes from users who are still in the guild
+    let entries = sql::get_vote_entries(challenge, week_num).await?;
+    let mut present = HashMap::new();
+    let mut counts = vec![0; week_info.num_subs.max(0) as usize];
+    for (user, idx) in entries {
+        if !present.contains_key(&user) {
+            present.insert(user, config().server_id.member(&ctx, user).await.is_ok());
+        }
+        if present[&user] { if let Some(count) = counts.get_mut(idx as usize) { *count += 1; } }
+    }
+
+    let mut embed = create_challenge_embed(&ctx, challenge).author(CreateEmbedAuthor::new(format!("{} Challenge Week {} Poll", challenge.name(), week_num)));
+    if counts.is_empty() {
+        embed = embed.description("This poll has no submissions.");
+    } else {
+        let lines: Vec<String> = counts.iter().enumerate().map(|(idx, count)| format!(
+            "{} – {} vote{}",
+            VOTING_EMOJI_SEQUENCE.get(idx).map(|x| x.display_string()).unwrap_or_else(|| format!("#{}", idx + 1)),
+            count,
+            if *count == 1 { "" } else { "s" }
+        )).collect();
+        let voters = present.values().filter(|x| **x).count();
+        embed = embed.description(safe_truncate(format!("{}\n\n{} voter{}", lines.join("\n"), voters, if voters == 1 { "" } else { "s" }), 4096));
+    }
+
+    ctx.send(CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+/// Show the weeks of a challenge that have finished.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn history(
+    ctx: Context<'_>,
+    #[description = "The challenge to show the history of"] challenge: Challenge,
+    #[description = "Only show weeks from this one onwards"] from_week: Option<i64>,
+    #[description = "Only show weeks up to and including this one"] to_week: Option<i64>,
+) -> Res {
+    const PAGE_SIZE: usize = 10;
+
+    let weeks: Vec<WeekInfo> = sql::get_weeks(challenge, -1, 0).await?.into_iter()
+        .filter(|w| from_week.map_or(true, |from| w.week_num >= from) && to_week.map_or(true, |to| w.week_num <= to))
+        .collect();
+    let num_pages = weeks.len().div_ceil(PAGE_SIZE).max(1);
+
+    let page_embed = |page: usize| {
+        let title = match num_pages {
+            1 => format!("{} Challenge History", challenge.name()),
+            _ => format!("{} Challenge History (page {} of {})", challenge.name(), page + 1, num_pages),
+        };
+        let mut embed = create_challenge_embed(&ctx, challenge).author(CreateEmbedAuthor::new(title));
+        if weeks.is_empty() { return embed.description("No weeks have finished yet."); }
+        for week in weeks.iter().skip(page * PAGE_SIZE).take(PAGE_SIZE) {
+            embed = embed.field(
+                safe_truncate(format!("**Week {}**: {}", week.week_num, week.prompt_string), 200),
+                format!("> {} submission{}{}", week.num_subs, if week.num_subs == 1 { "" } else { "s" },
+                    if week.is_special { "\n> special week" } else { "" }),
+                false,
+            );
+        }
+        embed
+    };
+
+    send_paginated(ctx, num_pages, page_embed).await
+}
+
+/// List a user's submissions, with links to them.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn submissions(
+    ctx: Context<'_>,
+    #[description = "The user whose submissions to list"] user: User,
+    #[description = "Only list submissions to this challenge"] challenge: Option<Challenge>,
+    #[description = "Only list submissions from this week onwards"] from_week: Option<i64>,
+    #[description = "Only list submissions up to and including this week"] to_week: Option<i64>,
+) -> Res {
+    const PAGE_SIZE: usize = 10;
+
+    // Look the challenges up one at a time so we know which channel to link to.
+    let mut entries = Vec::new();
+    for c in Challenge::all().filter(|c| challenge.map_or(true, |challenge| challenge == *c)) {
+        for (week_num, message, link) in sql::get_user_submissions(user.id, Some(c)).await? {
+            if from_week.map_or(true, |from| week_num >= from) && to_week.map_or(true, |to| week_num <= to) {
+                entries.push(format!("**{} week {}**: [message]({}) · [image]({})", c.name(), week_num,
+                    message_link(config().server_id, c.submission_channel(), message), link));
+            }
+        }
+    }
+    let num_pages = entries.len().div_ceil(PAGE_SIZE).max(1);
+
+    let page_embed = |page: usize| {
+        let title = match num_pages {
+            1 => format!("Submissions by {}", user.name),
+            _ => format!("Submissions by {} (page {} of {})", user.name, page + 1, num_pages),
+        };
+        let embed = match challenge {
+            Some(challenge) => create_challenge_embed(&ctx, challenge),
+            None => create_embed(&ctx),
+        }.author(CreateEmbedAuthor::new(title));
+        if entries.is_empty() { return embed.description(format!("<@{}> has no submissions matching these filters.", user.id)); }
+        embed.description(safe_truncate(entries.iter().skip(page * PAGE_SIZE).take(PAGE_SIZE)
+            .cloned().collect::<Vec<_>>().join("\n"), 4096))
+    };
+
+    send_paginated(ctx, num_pages, page_embed).await
+}
+
+/// Show totals across all weeks of a challenge.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn stats(
+    ctx: Context<'_>,
+    #[description = "The challenge to show statistics for"] challenge: Challenge,
+) -> Res {
+    let stats = sql::challenge_stats(challenge).await?;
+    let busiest_week = match stats.busiest_week {
+        Some((week_num, total)) => format!("Week {} ({} submission{})", week_num, total, if total == 1 { "" } else { "s" }),
+        None => "None yet".to_owned(),
+    };
+
+    let embed = create_challenge_embed(&ctx, challenge)
+        .author(CreateEmbedAuthor::new(format!("{} Challenge Statistics", challenge.name())))
+        .field("Weeks", stats.weeks.to_string(), true)
+        .field("Submissions", stats.submissions.to_string(), true)
+        .field("Participants", stats.participants.to_string(), true)
+        .field("Submissions per Week", format!("{:.1}", stats.average_submissions()), true)
+        .field("Most Submissions", busiest_week, true);
+    ctx.send(CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
 /// Update bot commands.
 #[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
 pub async fn update(ctx: Context<'_>) -> Res {
     register_application_commands(ctx, false).await?;
     Ok(())
@@ -340,6 +1432,7 @@ pub async fn update(ctx: Context<'_>) -> Res {
 pub async fn image(_ctx: Context<'_>) -> Res { unreachable!(); }
 
 #[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename="preview", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
 pub async fn image_preview(ctx: Context<'_>, 
     #[description="The challenge to preview an image for"] challenge: Challenge,
     #[description="The image to preview"] image_type: PreviewableImages,
@@ -348,8 +1441,8 @@ pub async fn image_preview(ctx: Context<'_>,
     ctx.defer_ephemeral().await?;
     let path = match image_type {
         PreviewableImages::Announcement => { 
-            let next_prompt_data = get_prompt_data(challenge, 1).await?;
-            let (week_num, start_time, end_time) = forecast_prompt_details(challenge, 1).await?;
+            let (position, _, next_prompt_data) = sql::get_next_prompt(challenge).await?;
+            let (week_num, start_time, end_time) = forecast_prompt_details(challenge, position as i64).await?;
             generate_challenge_image(challenge, week_num, 
                 Announcement { prompt_string: next_prompt_data.prompt_string , size_percentage: next_prompt_data.size_percentage.unwrap_or(100) }, 
                 start_time, end_time, raw.unwrap_or(false)).await? },
@@ -359,46 +1452,158 @@ pub async fn image_preview(ctx: Context<'_>,
             generate_challenge_image(challenge, week_num, Poll { prompt_string: week_info.prompt_string, 
                 size_percentage: week_info.size_percentage }, week_info.target_start_time, week_info.target_end_time, 
                 raw.unwrap_or(false)).await? },
-        PreviewableImages::FirstPlace => { unimplemented!() },
-        PreviewableImages::SecondPlace => { unimplemented!() },
-        PreviewableImages::ThirdPlace => {unimplemented!() },
+        PreviewableImages::FirstPlace => generate_winner_image(ctx, challenge, WinnerPosition::First, raw.unwrap_or(false)).await?,
+        PreviewableImages::SecondPlace => generate_winner_image(ctx, challenge, WinnerPosition::Second, raw.unwrap_or(false)).await?,
+        PreviewableImages::ThirdPlace => generate_winner_image(ctx, challenge, WinnerPosition::Third, raw.unwrap_or(false)).await?,
     };
 
     ctx.send(CreateReply::default()
-        .attachment(CreateAttachment::path(path).await?)
+        .attachment(take_challenge_image(&path).await?)
     ).await?;
 
     Ok(())
 }
 
+/// Generate the image for one of the winners of the week whose voting has most recently concluded.
+async fn generate_winner_image(ctx: Context<'_>, challenge: Challenge, position: WinnerPosition, raw: bool) -> ResT<String> {
+    let week_num = get_current_week_num(challenge).await? - 1;
+    generate_week_winner_image(ctx, challenge, week_num, position, raw).await
+}
+
+/// Generate the image for one of the winners of a given week, whose voting must have concluded.
+async fn generate_week_winner_image(ctx: Context<'_>, challenge: Challenge, week_num: i64, position: WinnerPosition, raw: bool) -> ResT<String> {
+    if !voting_concluded(challenge, week_num).await? {
+        return Err(format!("Voting for week {} has not concluded yet.", week_num).into());
+    }
+
+    let week_info = get_week_info(week_num, challenge).await?;
+    let winners = rank_winners(tally_week(challenge, week_num).await?);
+    let (submission_id, winner_id, _) = *winners.get(position.index())
+        .ok_or(format!("Not enough users received votes in week {} to award {} place.", week_num, position.name()))?;
+
+    // Prefer the nickname set with /nickname, falling back to the user's Discord name.
+    let winner_nick = match get_user_profile(winner_id).await?.nickname {
+        Some(nick) => nick,
+        None => {
+            let user = winner_id.to_user(ctx).await?;
+            user.global_name.unwrap_or(user.name)
+        }
+    };
+
+    generate_challenge_image(challenge, week_num, Winner { position, winner_nick, winner_id, submission_id },
+        week_info.target_start_time, week_info.target_end_time, raw).await
+}
+
+/// Upload an image to be posted instead of the generated one.
 #[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error", rename = "upload", default_member_permissions = "ADMINISTRATOR")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
 pub async fn image_upload(ctx: Context<'_>, 
     #[description="The challenge to upload an image for"] challenge: Challenge,
-    #[description="The image type to upload"] image_type: UploadableImages) -> Res {
-    
-    todo!()
+    #[description="The image type to upload"] image_type: UploadableImages,
+    #[description="The image to use instead of the generated one"] image: Attachment) -> Res {
+
+    // Same check as for submissions: only images have a height.
+    if image.height.is_none() {
+        return Err("The uploaded file must be an image.".into());
+    }
+
+    // The announcement that is posted next is for the week after the current one,
+    // while the poll that is posted next is for the current week.
+    let current_week_num = get_current_week_num(challenge).await?;
+    let week_num = match image_type {
+        UploadableImages::Announcement => current_week_num + 1,
+        UploadableImages::Poll => current_week_num,
+    };
+
+    ctx.defer_ephemeral().await?;
+    let path = save_image_override(&image, challenge, &image_type, week_num).await?;
+
+    let embed = create_challenge_embed(&ctx, challenge)
+        .author(CreateEmbedAuthor::new(format!("Image override for {} Challenge", challenge.name())))
+        .description(format!("The {} image for week {} will be replaced with the uploaded image when it is posted.", image_type.name(), week_num))
+        .field("Stored at", format!("`{}`", path), false);
+    ctx.send(CreateReply::default().embed(embed)).await?;
+    Ok(())
 }
 
-///// Show stats for a week.
+/// Show the prompt that is currently running.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn current(
+    ctx: Context<'_>,
+    #[description = "Which challenge to show the current prompt for"] challenge: Challenge,
+) -> Res {
+    let week_num = get_current_week_num(challenge).await?;
+    let info = get_week_info(week_num, challenge).await?;
+    let deadline = match info.actual_end_time {
+        Timestamp(None) => format!("{} ({})", info.target_end_time.to_discord('f'), info.target_end_time.to_discord('R')),
+        ended => format!("Submissions closed {}", ended.to_discord('R')),
+    };
+
+    let embed = create_challenge_embed(&ctx, challenge)
+        .author(CreateEmbedAuthor::new(format!("Week {} of the {} Challenge", week_num, challenge.name())))
+        .field("Prompt", &info.prompt_string, true)
+        .field("Submissions", get_submissions(challenge, week_num).await?.len().to_string(), true)
+        .field("Started", info.actual_start_time.to_discord('f'), false)
+        .field("Deadline", deadline, false);
+    ctx.send(CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+/// Show stats for a week.
 //
-// Info shown are: That week’s glyph/ambigram, message link to
-// that week’s announcement post, How many submissions there were
-// in that week, how many people voted for that week’s submissions,
-// message link to that week’s submissions post, top 3 winner names,
-// message link to that week’s hall of fame, & the announcement image
-// used for that week.
-// #[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error")]
-// pub async fn week_info(
-//     ctx: Context<'_>,
-//     #[description = "Which challenge to get stats for"] challenge: Challenge,
-//     #[description = "The week whose stats to retrieve"] week: Option<u64>,
-// ) -> Res {
-//     let info = sql::weekinfo(week).await?;
-//     let mut embed = create_embed(&ctx);
-//     embed = embed.author(CreateEmbedAuthor::new(format!("Stats for Week {}", info.week)));
-//     embed = embed.field("Submissions", format!("{}", info.submissions), true);
-//     todo!();
-
-
-//     Ok(())
-// }
\ No newline at end of file
+// Info shown are: That week’s glyph/ambigram, message links to
+// that week’s announcement & poll, how many submissions there were in that week,
+// how many people voted for that week’s submissions, the top 3
+// winners, & the announcement image used for that week.
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_command_error")]
+#[instrument(skip_all, fields(command = %ctx.command().qualified_name, user = %ctx.author().id))]
+pub async fn week_info(
+    ctx: Context<'_>,
+    #[description = "Which challenge to get stats for"] challenge: Challenge,
+    #[description = "The week whose stats to retrieve - defaults to the current week"] week: Option<i64>,
+) -> Res {
+    let week_num = match week {
+        Some(w) => w,
+        None => get_current_week_num(challenge).await?,
+    };
+    let info = get_week_info(week_num, challenge).await?;
+    ctx.defer_ephemeral().await?;
+
+    let mut embed = create_challenge_embed(&ctx, challenge)
+        .author(CreateEmbedAuthor::new(format!("Stats for Week {} of the {} Challenge", week_num, challenge.name())))
+        .field("Prompt", &info.prompt_string, true)
+        .field("Submissions", get_submissions(challenge, week_num).await?.len().to_string(), true)
+        .field("Voters", sql::count_voters(challenge, week_num).await?.to_string(), true)
+        .field("Dates", format!("{} – {}", info.target_start_time.to_discord('D'), info.target_end_time.to_discord('D')), false);
+
+    let announcement = match sql::get_announcement_message(challenge, week_num).await? {
+        Some(id) => message_link(config().server_id, challenge.announcement_channel(), id),
+        None => "Not posted.".to_owned(),
+    };
+    embed = embed.field("Announcement", announcement, false);
+
+    let poll_links: Vec<String> = info.poll_message_ids.0.iter()
+        .map(|id| message_link(config().server_id, challenge.announcement_channel(), *id))
+        .collect();
+    embed = embed.field("Poll", if poll_links.is_empty() { "Not posted yet.".to_owned() } else { poll_links.join("\n") }, false);
+
+    let winners = if voting_concluded(challenge, week_num).await? {
+        let lines: Vec<String> = rank_winners(tally_week(challenge, week_num).await?).into_iter().take(3).enumerate()
+            .map(|(idx, (_, user, votes))| format!("**{}.** <@{}> ({} vote{})", idx + 1, user, votes, if votes == 1 { "" } else { "s" }))
+            .collect();
+        if lines.is_empty() { "Nobody received any votes.".to_owned() } else { lines.join("\n") }
+    } else {
+        "Voting has not concluded yet.".to_owned()
+    };
+    embed = embed.field("Winners", winners, false);
+
+    let path = generate_challenge_image(challenge, week_num, Announcement { prompt_string: info.prompt_string,
+        size_percentage: info.size_percentage }, info.target_start_time, info.target_end_time, false).await?;
+
+    ctx.send(CreateReply::default()
+        .embed(embed)
+        .attachment(take_challenge_image(&path).await?)
+    ).await?;
+    Ok(())
+}
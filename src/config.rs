@@ -0,0 +1,189 @@
+//! Runtime configuration, read from `glyfi.toml` at startup. Anything that isn’t set
+//! there falls back to the ids compiled in from `server_data` or, for everything else,
+//! to the defaults below, so the file only needs to contain what differs for this
+//! instance of the bot.
+
+use crate::{info, server_data};
+use crate::core::DEFAULT_EMBED_COLOUR;
+use crate::types::AnyEmoji;
+use chrono::Duration;
+use chrono_tz::Tz;
+use poise::serenity_prelude::{ChannelId, Colour, EmojiId, GuildId, RoleId, UserId};
+use serde::{Deserialize, Deserializer};
+use std::sync::OnceLock;
+
+pub const CONFIG_PATH: &str = "glyfi.toml";
+
+static __GLYFI_CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// The ids, intervals and other settings that differ between servers. Durations are given in seconds.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server_id: GuildId,
+    pub glyfi_user_id: UserId,
+    pub glyph_announcements_channel_id: ChannelId,
+    pub ambigram_announcements_channel_id: ChannelId,
+    pub glyph_submission_channel_id: ChannelId,
+    pub ambigram_submission_channel_id: ChannelId,
+    pub status_update_channel_id: ChannelId,
+    pub submit_emoji_id: EmojiId,
+    /// Roles to ping when a new week of each challenge is announced, if any.
+    pub glyph_announcement_role_id: Option<RoleId>,
+    pub ambigram_announcement_role_id: Option<RoleId>,
+    /// Whether animated submissions keep their animation rather than just their first frame.
+    pub glyph_allow_animated: bool,
+    pub ambigram_allow_animated: bool,
+    /// How long before the deadline of each challenge to post a reminder in its submission channel, if at all.
+    #[serde(deserialize_with = "optional_seconds")]
+    pub glyph_reminder_lead_time: Option<Duration>,
+    #[serde(deserialize_with = "optional_seconds")]
+    pub ambigram_reminder_lead_time: Option<Duration>,
+    #[serde(deserialize_with = "seconds")]
+    pub glyph_interval: Duration,
+    #[serde(deserialize_with = "seconds")]
+    pub ambi_interval: Duration,
+    #[serde(deserialize_with = "seconds")]
+    pub time_gap: Duration,
+    #[serde(deserialize_with = "std_seconds")]
+    pub schedule_interval: std::time::Duration,
+    /// How long voting stays open after a week's submissions close. If this is not set,
+    /// the poll is posted when the next week starts and voting lasts until it ends.
+    #[serde(deserialize_with = "optional_seconds")]
+    pub voting_duration: Option<Duration>,
+    /// How many submissions a user may vote for in a single poll, if there is a limit.
+    pub max_votes_per_user: Option<i64>,
+    /// How many submissions a user may make in a single week, if there is a limit.
+    pub max_submissions_per_user: Option<i64>,
+    /// The reaction the bot adds to a submission once it is registered. Either a single
+    /// character, or a table with the `id` and `name` of a custom emoji.
+    #[serde(deserialize_with = "any_emoji")]
+    pub confirm_emoji: AnyEmoji,
+    /// How dates are written on challenge images, and in which timezone.
+    pub image_date_format: String,
+    #[serde(deserialize_with = "timezone")]
+    pub image_timezone: Tz,
+    /// How many times to try generating an image, and how long to wait before the first retry.
+    /// The wait doubles after every failed attempt.
+    pub generation_attempts: u32,
+    #[serde(deserialize_with = "std_seconds")]
+    pub generation_retry_delay: std::time::Duration,
+    /// Embed colours for each challenge, as 0xRRGGBB.
+    #[serde(deserialize_with = "colour")]
+    pub glyph_embed_colour: Colour,
+    #[serde(deserialize_with = "colour")]
+    pub ambigram_embed_colour: Colour,
+    /// The port metrics are served on, if the bot is built with the `metrics` feature.
+    pub metrics_port: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            server_id: server_data::SERVER_ID,
+            glyfi_user_id: server_data::GLYFI_USER_ID,
+            glyph_announcements_channel_id: server_data::GLYPH_ANNOUNCEMENTS_CHANNEL_ID,
+            ambigram_announcements_channel_id: server_data::AMBIGRAM_ANNOUNCEMENTS_CHANNEL_ID,
+            glyph_submission_channel_id: server_data::GLYPH_SUBMISSION_CHANNEL_ID,
+            ambigram_submission_channel_id: server_data::AMBIGRAM_SUBMISSION_CHANNEL_ID,
+            status_update_channel_id: server_data::STATUS_UPDATE_CHANNEL_ID,
+            submit_emoji_id: server_data::SUBMIT_EMOJI_ID,
+            glyph_announcement_role_id: None,
+            ambigram_announcement_role_id: None,
+            glyph_allow_animated: false,
+            ambigram_allow_animated: false,
+            glyph_reminder_lead_time: None,
+            ambigram_reminder_lead_time: None,
+            glyph_interval: server_data::GLYPH_INTERVAL,
+            ambi_interval: server_data::AMBI_INTERVAL,
+            time_gap: server_data::TIME_GAP,
+            schedule_interval: std::time::Duration::from_secs(60),
+            voting_duration: None,
+            max_votes_per_user: None,
+            max_submissions_per_user: None,
+            confirm_emoji: AnyEmoji::Default('✅'),
+            image_date_format: "%d/%m/%Y".to_owned(),
+            image_timezone: Tz::UTC,
+            generation_attempts: 3,
+            generation_retry_delay: std::time::Duration::from_secs(5),
+            glyph_embed_colour: DEFAULT_EMBED_COLOUR,
+            ambigram_embed_colour: Colour::from_rgb(107, 160, 199),
+            metrics_port: 9090,
+        }
+    }
+}
+
+fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    i64::deserialize(deserializer).map(Duration::seconds)
+}
+
+fn optional_seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    seconds(deserializer).map(Some)
+}
+
+fn std_seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<std::time::Duration, D::Error> {
+    u64::deserialize(deserializer).map(std::time::Duration::from_secs)
+}
+
+fn timezone<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Tz, D::Error> {
+    String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+}
+
+fn colour<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Colour, D::Error> {
+    u32::deserialize(deserializer).map(Colour::new)
+}
+
+fn any_emoji<'de, D: Deserializer<'de>>(deserializer: D) -> Result<AnyEmoji, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Emoji {
+        Default(char),
+        Custom { id: EmojiId, name: String },
+    }
+
+    // The config lives for the rest of the program anyway, so leaking the name is fine.
+    Ok(match Emoji::deserialize(deserializer)? {
+        Emoji::Default(c) => AnyEmoji::Default(c),
+        Emoji::Custom { id, name } => AnyEmoji::Custom(id, Box::leak(name.into_boxed_str())),
+    })
+}
+
+/// Get the global configuration.
+#[cfg(not(test))]
+pub fn config() -> &'static Config {
+    __GLYFI_CONFIG.get().expect("Config accessed before it was loaded")
+}
+
+/// Tests don’t load `glyfi.toml`; they always use the built-in config.
+#[cfg(test)]
+pub fn config() -> &'static Config {
+    __GLYFI_CONFIG.get_or_init(Config::default)
+}
+
+/// Get the bot token. This is deliberately not part of the config file, so that the file
+/// can be shared safely; it is read from the `DISCORD_BOT_TOKEN` environment variable instead.
+pub fn discord_bot_token() -> String {
+    std::env::var("DISCORD_BOT_TOKEN").unwrap_or_else(|_| server_data::DISCORD_BOT_TOKEN.to_owned())
+}
+
+/// Only intended to be called by main().
+pub fn __glyfi_load_config() {
+    let config = match std::fs::read_to_string(CONFIG_PATH) {
+        Ok(contents) => match toml::from_str(&contents) {
+            Ok(config) => {
+                info!("Loaded config from {}", CONFIG_PATH);
+                config
+            }
+            Err(e) => panic!("Failed to parse {}: {}", CONFIG_PATH, e),
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("No {} found; using the built-in config", CONFIG_PATH);
+            Config::default()
+        }
+        Err(e) => panic!("Failed to read {}: {}", CONFIG_PATH, e),
+    };
+
+    if __GLYFI_CONFIG.set(config).is_err() {
+        panic!("Config loaded twice");
+    }
+}
@@ -1,58 +1,50 @@
-use crate::sql::__glyfi_fini_db;
+use crate::file::__glyfi_stop_generations;
+use crate::sql::{self, __glyfi_fini_db};
+use crate::types::Challenge;
 use crate::{Context, Error, Res, __glyfi_terminate_bot};
-use chrono::Utc;
+use tracing_subscriber::EnvFilter;
 use poise::serenity_prelude::{
-    CacheHttp, Colour, CreateEmbed, CreateEmbedFooter, CreateMessage, UserId,
+    CacheHttp, ChannelId, Colour, CreateEmbed, CreateEmbedFooter, CreateMessage, GuildId, MessageId, UserId,
 };
 use poise::CreateReply;
-use std::sync::atomic::AtomicBool;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
 /// Default colour to use for embeds.
 pub const DEFAULT_EMBED_COLOUR: Colour = Colour::from_rgb(176, 199, 107);
 
+/// How long after reporting an error to a user we stay quiet about the same error.
+const USER_ERROR_DEBOUNCE: Duration = Duration::from_secs(30);
+
+/// When each user was last told about each error, for [`report_user_error()`].
+static RECENT_USER_ERRORS: OnceLock<Mutex<HashMap<(UserId, String), Instant>>> = OnceLock::new();
+
+/// Whether maintenance mode is on. While it is, submissions and the scheduler are paused.
+static MAINTENANCE_MODE: AtomicBool = AtomicBool::new(false);
+
 /// Logging macros. These macros log an informational or error
-/// message. Depending on the program stage, the message will
-/// be displayed in the terminal or sent to Discord; The `sync`
-/// variants always log to the terminal.
+/// message through `tracing`, so the output can be filtered with
+/// `RUST_LOG`.
 #[macro_export]
 macro_rules! info {
-    ($arg:expr) => { $crate::core::__glyfi_log_internal(&*($arg)).await };
-    ($fmt:literal $(,$arg:expr)*) => { $crate::core::__glyfi_log_internal(format!($fmt $(,$arg)*).as_str()).await };
-}
-
-#[macro_export]
-macro_rules! info_sync {
-    ($arg:expr) => { $crate::core::__glyfi_log_internal_sync(&*($arg)) };
-    ($fmt:literal $(,$arg:expr)*) => { $crate::core::__glyfi_log_internal_sync(format!($fmt $(,$arg)*).as_str()) };
+    ($arg:expr) => { ::tracing::info!("{}", &*($arg)) };
+    ($fmt:literal $(,$arg:expr)*) => { ::tracing::info!($fmt $(,$arg)*) };
 }
 
 #[macro_export]
 macro_rules! err {
-    ($arg:expr) => { $crate::core::__glyfi_log_internal_error(&*($arg)).await };
-    ($fmt:literal $(,$arg:expr)*) => { $crate::core::__glyfi_log_internal_error(format!($fmt $(,$arg)*).as_str()).await };
-}
-
-#[macro_export]
-macro_rules! err_sync {
-    ($arg:expr) => { $crate::core::__glyfi_log_internal_error_sync(&*($arg)) };
-    ($fmt:literal $(,$arg:expr)*) => { $crate::core::__glyfi_log_internal_error_sync(format!($fmt $(,$arg)*).as_str()) };
-}
-
-/// Logging.
-pub async fn __glyfi_log_internal_error(e: &str) {
-    eprintln!("[Error@{}]: {}", Utc::now().format("%H:%M:%S%.3fZ"), e);
-}
-
-pub async fn __glyfi_log_internal(e: &str) {
-    eprintln!("[Info@{}]: {}", Utc::now().format("%H:%M:%S%.3fZ"), e);
-}
-
-pub fn __glyfi_log_internal_error_sync(e: &str) {
-    eprintln!("[Error@{}]: {}", Utc::now().format("%H:%M:%S%.3fZ"), e);
+    ($arg:expr) => { ::tracing::error!("{}", &*($arg)) };
+    ($fmt:literal $(,$arg:expr)*) => { ::tracing::error!($fmt $(,$arg)*) };
 }
 
-pub fn __glyfi_log_internal_sync(e: &str) {
-    eprintln!("[Info@{}]: {}", Utc::now().format("%H:%M:%S%.3fZ"), e);
+/// Set up logging. Defaults to showing our own informational messages and
+/// warnings from everything else; set `RUST_LOG` to override this.
+pub fn init_logging() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn,glyfi=info")))
+        .init();
 }
 
 /// Create an embed with some default settings applied to id.
@@ -73,6 +65,11 @@ pub fn create_embed(ctx: &Context<'_>) -> CreateEmbed {
     return embed;
 }
 
+/// Create an embed like [`create_embed`], coloured according to the challenge it is about.
+pub fn create_challenge_embed(ctx: &Context<'_>, challenge: Challenge) -> CreateEmbed {
+    create_embed(ctx).colour(challenge.colour())
+}
+
 /// Get the mtime of a file.
 pub fn file_mtime(path: &str) -> Result<u64, Error> {
     Ok(std::fs::metadata(path)?
@@ -132,10 +129,47 @@ pub async fn log_command(ctx: Context<'_>) {
     );
 }
 
+/// Check whether maintenance mode is on.
+pub fn maintenance_mode() -> bool {
+    MAINTENANCE_MODE.load(Ordering::SeqCst)
+}
+
+/// Turn maintenance mode on or off. The setting is saved to the database, so it survives restarts.
+pub async fn set_maintenance_mode(enabled: bool) -> Res {
+    sql::set_maintenance_mode(enabled).await?;
+    if MAINTENANCE_MODE.swap(enabled, Ordering::SeqCst) != enabled {
+        info!("Maintenance mode turned {}", if enabled { "ON: submissions and the scheduler are paused" } else { "OFF" });
+    }
+    Ok(())
+}
+
+/// Only to be called on startup, after the database has been initialised.
+pub async fn __glyfi_load_maintenance_mode() {
+    let enabled = sql::get_maintenance_mode().await.unwrap();
+    MAINTENANCE_MODE.store(enabled, Ordering::SeqCst);
+    if enabled { info!("Maintenance mode is ON: submissions and the scheduler are paused."); }
+}
+
 /// Report an error resulting from a user misusing a command/function.
+///
+/// If the same user was already sent the same error recently, it isn’t sent again, so
+/// e.g. spamming invalid reactions doesn’t flood them with DMs.
 pub async fn report_user_error(ctx: impl CacheHttp, user: UserId, s: &str) {
     info!("User error ({}): {}", user, s);
 
+    // Skip errors this user was told about recently. Expired entries are dropped
+    // here too, so the map doesn’t keep growing.
+    {
+        let now = Instant::now();
+        let mut recent = RECENT_USER_ERRORS.get_or_init(Default::default).lock().unwrap();
+        recent.retain(|_, sent| now.duration_since(*sent) < USER_ERROR_DEBOUNCE);
+        if recent.contains_key(&(user, s.to_owned())) {
+            info!("Not reporting repeated error to {}", user);
+            return;
+        }
+        recent.insert((user, s.to_owned()), now);
+    }
+
     // Helper for error handling.
     async fn aux(ctx: &impl CacheHttp, user: UserId, s: &str) -> Res {
         // Attempt to DM the user about this.
@@ -151,6 +185,13 @@ pub async fn report_user_error(ctx: impl CacheHttp, user: UserId, s: &str) {
     };
 }
 
+/// Link to a message in a guild channel. Unlike `Message::link()`, this doesn’t depend on the
+/// message knowing its guild, which it doesn’t if it was fetched over HTTP, so the link never
+/// points to a DM by mistake.
+pub fn message_link(guild: GuildId, channel: ChannelId, message: MessageId) -> String {
+    format!("https://discord.com/channels/{}/{}/{}", guild, channel, message)
+}
+
 /// Truncate a string w/o panicking.
 pub fn safe_truncate(mut s: String, mut len: usize) -> String {
     if s.len() <= len {
@@ -190,18 +231,21 @@ pub async fn terminate() {
     }
 
     // Shutdown asynchronously running code.
-    unsafe {
-        /*info_sync!("Shutting down worker tasks...");
-        if let Some(tsk) = TASK.as_ref() { tsk.abort_handle().abort(); }*/
+    /*info!("Shutting down worker tasks...");
+    if let Some(tsk) = TASK.as_ref() { tsk.abort_handle().abort(); }*/
 
-        info_sync!("Shutting down bot...");
-        __glyfi_terminate_bot().await;
+    info!("Shutting down bot...");
+    __glyfi_terminate_bot().await;
 
-        info_sync!("Shutting down DB...");
-        __glyfi_fini_db().await;
-    }
+    // `process::exit()` doesn't run destructors, so kill any running
+    // image generation processes ourselves rather than orphaning them.
+    info!("Stopping image generation...");
+    __glyfi_stop_generations().await;
+
+    info!("Shutting down DB...");
+    __glyfi_fini_db().await;
 
     // Exit the process.
-    info_sync!("Exiting...");
+    info!("Exiting...");
     std::process::exit(0);
 }
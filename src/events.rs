@@ -1,22 +1,27 @@
 use std::arch::x86_64;
 use std::sync::Arc;
-use std::collections::HashMap;
-use crate::core::report_user_error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::core::{maintenance_mode, message_link, report_user_error};
 use crate::file::download_pfp;
+use crate::metrics::{self, Metric};
 use crate::scheduling::schedule_loop;
-use crate::server_data::{
-    AMBIGRAM_ANNOUNCEMENTS_CHANNEL_ID, AMBIGRAM_SUBMISSION_CHANNEL_ID, GLYFI_USER_ID, GLYPH_ANNOUNCEMENTS_CHANNEL_ID, GLYPH_SUBMISSION_CHANNEL_ID, SUBMIT_EMOJI_ID, VOTING_EMOJI_SEQUENCE
-};
-use crate::sql::{check_submission, check_user, get_current_week_num, get_votes, get_week_info, register_user, register_vote};
-use crate::types::{AnyEmoji, Challenge, Timestamp, UserVoteReplyStatus, UserVoteStatusData, WeekInfo, NULL_TIMESTAMP};
-use crate::{err, file, info, info_sync, sql, Res, ResT};
+use crate::config::config;
+use crate::server_data::VOTING_EMOJI_SEQUENCE;
+use crate::sql::{check_submission, check_user, count_user_submissions, get_current_week_num, get_votes, get_week_info, register_user, register_vote};
+use crate::types::{AnyEmoji, Challenge, NotAnImageError, RemovedSubmission, RemovedSubmissionData, Timestamp, UserVoteReplyStatus, UserVoteStatusData, VoteLimitError, WeekInfo, NULL_TIMESTAMP};
+use crate::{err, file, info, sql, Error, Res, ResT};
 use chrono::{Duration, Utc};
 use poise::serenity_prelude::*;
-use tokio::sync::RwLock;
 use tokio::time;
 
 pub struct GlyfiEvents;
 
+/// How many minutes after removing a submission its author can still restore it.
+const UNDO_WINDOW_MINUTES: i64 = 5;
+
+/// Whether the scheduler has been started yet.
+static SCHEDULER_STARTED: AtomicBool = AtomicBool::new(false);
+
 /// Execute code and notify the user if execution fails.
 macro_rules! run {
     ($ctx:expr, $user:expr, $code:expr, $msg:expr) => {
@@ -32,25 +37,90 @@ macro_rules! run {
     }
 }
 
+/// Sent to users who try to add or remove a submission while maintenance mode is on.
+const MAINTENANCE_NOTICE: &str = "Glyfi is down for maintenance, so submissions can't be added or removed right now. Please try again later.";
+
 /// Get the confirm emoji.
 fn confirm_reaction() -> ReactionType {
-    return ReactionType::Unicode("✅".into());
+    config().confirm_emoji.into()
+}
+
+/// Parse the custom_id of an undo button, which has the form `undo-{message_id}`, into the id of the
+/// submission message. Returns `None` if it is not of that form.
+fn parse_undo_custom_id(custom_id: &str) -> Option<MessageId> {
+    custom_id.strip_prefix("undo-")?.parse::<u64>().ok().filter(|id| *id != 0).map(MessageId::new)
 }
 
-/// Helper function for interaction handler. Gets the relevant information from a button's custom_id,
-/// and fetches the current week, so we get one convenient ResT with all the data that could cause errors.
-async fn parse_button_data_get_current_week(custom_id: &str) -> ResT<(Challenge, i64, i64, WeekInfo)> {
-    let mut iter = custom_id.chars();
-    let challenge_char = iter.next().ok_or("Empty custom_id on button.".to_string())?;
-    let challenge = match challenge_char { 'g' => Ok(Challenge::Glyph), 'a' => Ok(Challenge::Ambigram), _ => {
-        Err(format!("Not a valid challenge name: {}", challenge_char).to_string()) } }?;
-    let button_week_num_str = iter.by_ref().take(4).collect::<String>();
-    let button_week_num = button_week_num_str.parse::<i64>()?;
-    let sub_num_str = iter.skip(1).take(3).collect::<String>();
-    let sub_num = sub_num_str.parse::<i64>()?;
+/// Restore a submission that its author removed, if they ask to do so in time.
+async fn handle_undo(i: &ComponentInteraction, ctx: &Context, message_id: MessageId) -> Res {
+    let lock = {
+        let data_read = ctx.data.read().await;
+        data_read.get::<RemovedSubmissionData>().ok_or("Couldn't get removed submission data")?.clone()
+    };
+    // take the entry out straight away, so that the same removal can't be undone twice
+    let removed = lock.write().await.remove(&message_id)
+        .filter(|removed| removed.author == i.user.id && removed.expires > Utc::now().timestamp_millis());
+    let respond = |content: &str| CreateInteractionResponse::UpdateMessage(CreateInteractionResponseMessage::new()
+        .content(content)
+        .components(vec![]));
+    let Some(removed) = removed else {
+        i.create_response(ctx, respond("This removal can no longer be undone.")).await?;
+        return Ok(());
+    };
+
+    // the week may have ended in the meantime
+    let current_week_info = get_current_week_info(removed.challenge).await?;
+    if current_week_info.week_num != removed.week_num || current_week_info.actual_end_time != NULL_TIMESTAMP {
+        i.create_response(ctx, respond("This removal can no longer be undone, since the week has ended.")).await?;
+        return Ok(());
+    }
+
+    let message = removed.channel.message(ctx, message_id).await?;
+    let att = message.attachments.first().ok_or("Submission no longer has an attachment")?;
+    info!("Restoring submission {} from {} for challenge {:?}", message_id, removed.author, removed.challenge);
+    sql::register_submission(message_id, removed.challenge, removed.author, &att.url, removed.week_num).await?;
+    file::download_submission(att, message_id, removed.challenge, removed.week_num).await?;
+    if let Err(e) = message.react(ctx, confirm_reaction()).await {
+        err!("Error reacting to restored submission: {}", e);
+    }
+    i.create_response(ctx, respond("Your submission has been restored.")).await?;
+    Ok(())
+}
+
+/// Let the author of a submission know that it was removed, and offer to undo the removal.
+async fn offer_undo(ctx: &Context, message: &Message, challenge: Challenge, week_num: i64) -> Res {
+    let lock = {
+        let data_read = ctx.data.read().await;
+        data_read.get::<RemovedSubmissionData>().ok_or("Couldn't get removed submission data")?.clone()
+    };
+    let expires = Utc::now() + Duration::minutes(UNDO_WINDOW_MINUTES);
+    lock.write().await.insert(message.id, RemovedSubmission { challenge, week_num, author: message.author.id,
+        channel: message.channel_id, expires: expires.timestamp_millis() });
+
+    let dm = message.author.id.create_dm_channel(ctx).await?;
+    dm.send_message(ctx, CreateMessage::new()
+        .content(format!("Your submission {} has been removed from the {} challenge. You can restore it until <t:{}:t>.",
+            message_link(config().server_id, message.channel_id, message.id), challenge.name(), expires.timestamp()))
+        .button(CreateButton::new(format!("undo-{}", message.id)).style(ButtonStyle::Secondary).label("Undo"))
+    ).await?;
+    Ok(())
+}
+
+/// Parse the custom_id of a voting button, which has the form `{one_char_name}{week:04}-{idx:03}`,
+/// into its challenge, week number and submission index. Returns `None` if it is not of that form.
+fn parse_button_custom_id(custom_id: &str) -> Option<(Challenge, i64, i64)> {
+    let bytes = custom_id.as_bytes();
+    if bytes.len() != 9 || bytes[5] != b'-' { return None; }
+    if !bytes[1..5].iter().chain(&bytes[6..9]).all(u8::is_ascii_digit) { return None; }
+    let challenge = Challenge::from_one_char_name(bytes[0] as char)?;
+    Some((challenge, custom_id[1..5].parse().ok()?, custom_id[6..9].parse().ok()?))
+}
+
+/// Helper function for interaction handler. Fetches the current week, so we get one convenient ResT
+/// with all the data that could cause errors.
+async fn get_current_week_info(challenge: Challenge) -> ResT<WeekInfo> {
     let current_week_num = get_current_week_num(challenge).await?;
-    let current_week_info = get_week_info(current_week_num, challenge).await?;
-    Ok((challenge, button_week_num, sub_num, current_week_info))
+    get_week_info(current_week_num, challenge).await
 }
 
 /// Does most of the logic of handling votes, including replying to the interaction generated by interaction_create.
@@ -77,8 +147,16 @@ async fn handle_vote(i: ComponentInteraction, ctx: Context, challenge: Challenge
                 let timestamp = Utc::now().timestamp_millis() + 2000;
                 vote_status_data.insert(user_id, Waiting(timestamp));
                 info!("Wait time for user {} has been updated to {} by vote for sub {}:{}", user_id, timestamp, challenge.short_name(), sub_num);
-                if !register_vote(challenge, week_num, user_id, sub_num).await? {
-                    info!("Database operation was not successful when registering vote.");
+                match register_vote(challenge, week_num, user_id, sub_num).await {
+                    Ok(true) => metrics::increment(Metric::VotesCast),
+                    Ok(false) => info!("Database operation was not successful when registering vote."),
+                    Err(e) => match e.downcast_ref::<VoteLimitError>() {
+                        // let the user know, then fall through to showing them their current votes
+                        Some(limit_error) => { i.create_followup(&ctx, CreateInteractionResponseFollowup::new()
+                            .content(limit_error.to_string())
+                            .ephemeral(true)).await?; },
+                        None => return Err(e),
+                    }
                 }
                 break;
             }
@@ -110,7 +188,8 @@ async fn handle_vote(i: ComponentInteraction, ctx: Context, challenge: Challenge
                     tokio::time::sleep(tokio::time::Duration::from_millis(500));
 
                     // respond with the current state of the user's votes
-                    let votes : Vec<String> = get_votes(challenge, week_num, user_id, num_subs).await?.iter().map(|x| VOTING_EMOJI_SEQUENCE.get(*x as usize).map(|x| x.display_string())).flatten().collect();
+                    let votes : Vec<String> = get_votes(challenge, week_num, user_id, num_subs).await?.iter().map(|x| VOTING_EMOJI_SEQUENCE.get(*x as usize)
+                        .map(|x| x.display_string()).unwrap_or_else(|| format!("#{}", x + 1))).collect();
                     i.create_followup(&ctx, CreateInteractionResponseFollowup::new()
                         .content(format!("Your current votes: {}", votes.join(" ")))
                         .ephemeral(true)).await;
@@ -152,9 +231,16 @@ impl EventHandler for GlyfiEvents {
         match interaction {
             Interaction::Command(_) => { return; },
             Interaction::Component(mut i) => {
-                let custom_id = i.data.custom_id.clone();
                 let user_id = i.user.id;
-                let (challenge, button_week_num, sub_num, current_week_info) = match parse_button_data_get_current_week(&custom_id).await { 
+                if let Some(message_id) = parse_undo_custom_id(&i.data.custom_id) {
+                    if let Err(e) = handle_undo(&i, &ctx, message_id).await {
+                        err!("Error undoing removal of submission {}: {}", message_id, e);
+                    }
+                    return;
+                }
+                // other components (e.g. confirmation buttons on command replies) are handled elsewhere
+                let Some((challenge, button_week_num, sub_num)) = parse_button_custom_id(&i.data.custom_id) else { return; };
+                let week_info = match get_week_info(button_week_num, challenge).await { 
                     Ok(x) => x, Err(e) => { if let Err(e) = i.create_response(ctx, CreateInteractionResponse::Message(
                         CreateInteractionResponseMessage::new()
                             .content(format!("Internal error: {:?}. Contact @sungodmoth to report this error.", e))
@@ -162,13 +248,10 @@ impl EventHandler for GlyfiEvents {
                     ).await { err!(format!("Error reporting button interaction error to user: {e}")) }; return; 
                     }
                 };
-                if current_week_info.week_num == button_week_num + 1 && current_week_info.actual_end_time == NULL_TIMESTAMP {
+                if week_info.voting_open() {
                     info!("Current vote's {}th submission has been voted for by {}", sub_num + 1, user_id);
-                    let num_subs = match get_week_info(button_week_num, challenge).await {
-                        Ok(x) => x, Err(e) => { err!(format!("Error getting week data for week {}:{}: {}", challenge.short_name(), button_week_num, e)); return; }
-                    }.num_subs;
                     // pass off execution
-                    if let Err(e) = handle_vote(i, ctx, challenge, sub_num, button_week_num, num_subs).await {
+                    if let Err(e) = handle_vote(i, ctx, challenge, sub_num, button_week_num, week_info.num_subs).await {
                         err!(format!("Error handling vote: {}", e)); return; }
                 } else {
                     if let Err(e) = i.create_response(ctx, CreateInteractionResponse::Message(
@@ -201,22 +284,21 @@ impl EventHandler for GlyfiEvents {
             return;
         };
         let user_id = member.user.id;
-        if user_id == GLYFI_USER_ID { return; }
+        if user_id == config().glyfi_user_id { return; }
         
         // Ignore this outside of the submission channels.
-        let challenge = match r.channel_id {
-            GLYPH_SUBMISSION_CHANNEL_ID => Challenge::Glyph,
-            AMBIGRAM_SUBMISSION_CHANNEL_ID => Challenge::Ambigram,
-            _ => { return; },
-        };
+        let Some(challenge) = Challenge::from_submission_channel(r.channel_id) else { return; };
         // Ignore anything that isn’t the emoji we care about.
         if !matches!(
             r.emoji,
-            ReactionType::Custom {
-                id: SUBMIT_EMOJI_ID,
-                ..
-            }
+            ReactionType::Custom { id, .. } if id == config().submit_emoji_id
         ) { return; };
+
+        // Take the reaction back off, so it can simply be added again once maintenance is over.
+        if maintenance_mode() {
+            report_user_error(&ctx, user_id, MAINTENANCE_NOTICE).await;
+            remove_reaction!(ctx, r);
+        }
         
         let Ok(current_week_num) = get_current_week_num(challenge).await else { return; };
         let Ok(current_week_info) = get_week_info(current_week_num, challenge).await else { return; };
@@ -230,16 +312,30 @@ impl EventHandler for GlyfiEvents {
         // want to make sure that we care about this reaction event before we call it
         let Ok(message) = r.message(&ctx).await else { return; };
 
-        // make sure the message was posted during this challenge
-        // actual_start_time should always be set for the current week
-        let message_timestamp: Timestamp = message.timestamp.unix_timestamp().try_into().unwrap();
-        if message_timestamp < current_week_info.actual_start_time { return; }
-
         // If someone reacted w/ this emoji to someone else’s message, remove it.
         if user_id != message.author.id {
             remove_reaction!(ctx, r);
         }
 
+        // Discord sometimes delivers the same reaction again, e.g. after reconnecting; there is
+        // nothing to do if we already have the submission.
+        match check_submission(message.id).await {
+            Ok(true) => { info!("Submission {} is already registered", message.id); return; }
+            Ok(false) => {}
+            Err(e) => { err!("Error checking for submission {}: {}", message.id, e); }
+        }
+
+        // make sure the message was posted during this challenge, so that old messages can't
+        // be snuck into the current week; actual_start_time should always be set for the current week
+        let message_timestamp: Timestamp = message.timestamp.unix_timestamp().try_into().unwrap();
+        if message_timestamp < current_week_info.actual_start_time || message_timestamp > current_week_info.target_end_time {
+            report_user_error(&ctx, user_id, &format!(
+                "Only messages posted during the current week (since {}) can be submitted",
+                current_week_info.actual_start_time.to_discord('f')
+            )).await;
+            remove_reaction!(ctx, r);
+        }
+
         // Check the message for attachments.
         if message.attachments.len() != 1 {
             report_user_error(&ctx, user_id, "Submissions must contain exactly one image").await;
@@ -251,46 +347,80 @@ impl EventHandler for GlyfiEvents {
 
         // Error if the attachment is not an image.
         //
-        // There doesn’t really seem to be a way of checking what an attachment
-        // actually is (excepting checking the mime type, which I’m not willing
-        // to do), so checking whether the height exists, which it only should
-        // for images, will have to do.
-        if att.height.is_none() {
+        // This goes by the content type Discord reports, or, if there is none, by whether
+        // the attachment has a height, which it only should for images. The file itself
+        // is checked once we download it.
+        let is_image = match &att.content_type {
+            Some(content_type) => content_type.starts_with("image/"),
+            None => att.height.is_some(),
+        };
+        if !is_image {
             report_user_error(&ctx, user_id, "Submissions must contain only images").await;
             remove_reaction!(ctx, r);
         }
 
+        // Enforce the submission limit, if there is one.
+        if let Some(limit) = config().max_submissions_per_user {
+            match count_user_submissions(challenge, current_week_num, user_id).await {
+                Ok(count) if count >= limit => {
+                    report_user_error(&ctx, user_id, &format!(
+                        "You have already made {} of your {} submissions this week. Remove one before submitting another.",
+                        count, limit
+                    )).await;
+                    remove_reaction!(ctx, r);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    err!("Error counting submissions of user {}: {}", user_id, e);
+                    remove_reaction!(ctx, r);
+                }
+            }
+        }
+
         info!(
             "Adding submission {} from {} for challenge {:?}",
             message.id, user_id, challenge
         );
 
+        // Download the file first, so that we know it really is an image before registering it.
+        if let Err(e) = file::download_submission(att, message.id, challenge, current_week_num).await {
+            if let Some(not_an_image) = e.downcast_ref::<NotAnImageError>() {
+                report_user_error(&ctx, user_id, &not_an_image.to_string()).await;
+                remove_reaction!(ctx, r);
+            }
+            // Nothing has been saved, so the user can simply try again.
+            err!("Error downloading submission {}: {}", message.id, e);
+            report_user_error(&ctx, user_id, "Your submission couldn’t be saved because its image failed to download. \
+                Your reaction has been removed; please add it again to resubmit.").await;
+            remove_reaction!(ctx, r);
+        }
+
         run!(
             ctx,
             user_id,
             async {
-                sql::register_submission(message.id, challenge, user_id, &att.url, current_week_num)
-                    .await?;
-                file::download_submission(att, message.id, challenge, current_week_num).await
+                match sql::register_submission(message.id, challenge, user_id, &att.url, current_week_num).await {
+                    Ok(true) => metrics::increment(Metric::SubmissionsRegistered),
+                    // the same reaction was handled concurrently, so the file belongs to that submission
+                    Ok(false) => info!("Submission {} was registered concurrently", message.id),
+                    Err(e) => {
+                        // don't leave the file of a submission we don't know about lying around
+                        let _ = file::delete_submission(message.id, challenge, current_week_num).await;
+                        return Err(e);
+                    }
+                }
+                Ok(())
             }
             .await,
             "Error adding submission"
         );
 
-        match check_user(&member).await {
-            Ok(false) => {
-                if let Err(e) = download_pfp(&member).await {
-                    err!("Error downloading user pfp: {}", e);
-                }
-                //the user isn't in the database
-                if let Err(e) = register_user(member).await {
-                    err!("Error adding user to database: {}", e);
-                }
-            }
-            Err(e) => {
-                err!("Error checking whether user is in database: {}", e)
-            }
-            _ => {}
+        // both of these do nothing if we already know about the user
+        if let Err(e) = download_pfp(&member).await {
+            err!("Error downloading user pfp: {}", e);
+        }
+        if let Err(e) = register_user(member).await {
+            err!("Error adding user to database: {}", e);
         }
 
         // Done.
@@ -303,20 +433,17 @@ impl EventHandler for GlyfiEvents {
         // Check if we care about this.
         let Some(user_id) = r.user_id else { return; };
         // Ignore this outside of the submission channels.
-        let challenge = match r.channel_id {
-            GLYPH_SUBMISSION_CHANNEL_ID => Challenge::Glyph,
-            AMBIGRAM_SUBMISSION_CHANNEL_ID => Challenge::Ambigram,
-            _ => { return; },
-        };
+        let Some(challenge) = Challenge::from_submission_channel(r.channel_id) else { return; };
         // Ignore anything that isn’t the emoji we care about.
         if !matches!(
             r.emoji,
-            ReactionType::Custom {
-                id: SUBMIT_EMOJI_ID,
-                ..
-            }
+            ReactionType::Custom { id, .. } if id == config().submit_emoji_id
         ) { return; };
-        
+
+        if maintenance_mode() {
+            report_user_error(&ctx, user_id, MAINTENANCE_NOTICE).await;
+            return;
+        }
         
         let Ok(current_week_num) = get_current_week_num(challenge).await else { return; };
         let Ok(current_week_info) = get_week_info(current_week_num, challenge).await else { return; };
@@ -362,6 +489,9 @@ impl EventHandler for GlyfiEvents {
                     .await,
                     "Error removing submission"
                 );
+                if let Err(e) = offer_undo(&ctx, &message, challenge, current_week_num).await {
+                    err!("Error offering to undo removal of submission {}: {}", message.id, e);
+                }
             }
             Err(e) => {
                 err!("Error checking whether submission exists: {}", e);
@@ -369,12 +499,14 @@ impl EventHandler for GlyfiEvents {
             _ => {}
         }
 
-        // Remove our confirmation reaction. This is allowed to fail in case
-        // it was already removed somehow.
-        let me = ctx.cache.current_user().id;
-        let _ = message
-            .delete_reaction(ctx, Some(me), confirm_reaction())
-            .await;
+        // Remove our confirmation reaction, if it is still there. This is allowed
+        // to fail in case it was removed in the meantime.
+        if message.reactions.iter().any(|x| x.me && config().confirm_emoji == x.reaction_type) {
+            let me = ctx.cache.current_user().id;
+            let _ = message
+                .delete_reaction(ctx, Some(me), confirm_reaction())
+                .await;
+        }
     }
 
     async fn guild_member_update(
@@ -402,20 +534,23 @@ impl EventHandler for GlyfiEvents {
     }
 
     async fn ready(&self, ctx: Context, ready: Ready) {
-        info_sync!("Glyfi running with id {}", ready.user.id);
-        {
-            let mut data = ctx.data.write().await;
-            data.insert::<UserVoteStatusData>(Arc::new(RwLock::new(HashMap::new())));
-        }
+        info!("Glyfi running with id {}", ready.user.id);
+        // `ready` is sent again whenever we reconnect, but we only ever want one scheduler running.
+        if SCHEDULER_STARTED.swap(true, Ordering::SeqCst) { return; }
+
         //approach shamelessly copied from https://github.com/serenity-rs/serenity/blob/current/examples/e13_parallel_loops
+        info!("Starting scheduler, running every {}s", config().schedule_interval.as_secs());
         let ctx = Arc::new(ctx);
         let clone = Arc::clone(&ctx);
         tokio::spawn(async move {
+            let mut interval = time::interval(config().schedule_interval);
+            interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
             loop {
+                interval.tick().await;
+                // log and carry on, so that one failure doesn't stop the scheduler for good
                 if let Err(e) = schedule_loop(&clone).await {
                     err!("Error in schedule loop: {}", e)
                 };
-                time::sleep(time::Duration::from_secs(10)).await
         }});
     }
 }
@@ -1,26 +1,65 @@
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Output, Stdio};
+use std::sync::{atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering}, OnceLock};
 use chrono::{DateTime, Utc};
-use poise::serenity_prelude::{Attachment, Member, MessageId};
+use chrono_tz::Tz;
+use sha2::{Digest, Sha256};
+use poise::serenity_prelude::{Attachment, CreateAttachment, Member, MessageId};
 use tokio::{
     fs::{self, remove_file, File},
     io::AsyncWriteExt,
+    select,
+    sync::Notify,
 };
 
-use crate::{info, types::{ChallengeImageOptions, Timestamp}, Res, ResT};
+use crate::{core::file_mtime, err, info, types::{ChallengeImageOptions, NotAnImageError, Timestamp, UploadableImages}, Error, Res, ResT};
+use crate::metrics::{self, Metric};
 use crate::types::Challenge;
+use crate::config::config;
 
-/// Download a submission's image file to the file system
+/// How many times to try downloading an attachment before giving up.
+const DOWNLOAD_ATTEMPTS: u32 = 3;
+
+/// Download an attachment, retrying a few times, since failures are usually network blips.
+async fn download_attachment(attachment: &Attachment) -> ResT<Vec<u8>> {
+    let mut attempt = 1;
+    loop {
+        match attachment.download().await {
+            Ok(content) => return Ok(content),
+            Err(e) if attempt < DOWNLOAD_ATTEMPTS => {
+                err!("Failed to download {} (attempt {}/{}): {}", attachment.url, attempt, DOWNLOAD_ATTEMPTS, e);
+                tokio::time::sleep(std::time::Duration::from_secs(attempt as u64)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Download a submission's image file to the file system, unless it is already there. Fails with
+/// a [`NotAnImageError`] if the file isn't actually an image. If this fails, no file is left behind.
+///
+/// Animated images are saved as an apng if the challenge allows animation, and as their first
+/// frame otherwise.
 pub async fn download_submission(
     attachment: &Attachment,
     message_id: MessageId,
     challenge: Challenge,
     week_num: i64,
 ) -> Res {
-    let content = attachment.download().await?;
-    let short_name = challenge.short_name();
+    if submission_exists(message_id, challenge, week_num).await? {
+        info!("Submission {} has already been downloaded", message_id);
+        return Ok(());
+    }
+    let content = download_attachment(attachment).await?;
+    // check the file itself, since its name and the content type Discord reports can be wrong
+    if !infer::is_image(&content) {
+        return Err(NotAnImageError { detected: infer::get(&content).map(|kind| kind.mime_type()) }.into());
+    }
     //we don't actually have to care about the file extension in the name since we're converting anyway
     // let extension = attachment.filename.split('.').last().ok_or("File doesn't have an extension.")?;
     let extension = "png";
-    let dir = format!("generation/images/{short_name}/{week_num}");
+    let dir = submission_dir(challenge, week_num);
     fs::create_dir(&dir).await.or_else(|err| {
         if err.kind() == std::io::ErrorKind::AlreadyExists {
             Ok(())
@@ -28,53 +67,139 @@ pub async fn download_submission(
             Err(err)
         }
     })?;
-    let prefix = format!("{dir}/{message_id}");
+    let prefix = submission_prefix(message_id, challenge, week_num);
     let location = format!("{}.{}", prefix, extension);
     info!("Saving submission file to {}", location);
-    let mut file = File::create(&location).await?;
-    file.write_all(&content).await?;
-    info!("Converting {} to png...", location);
-    convert_image_type(&prefix, extension, "png").await?;
-    Ok(())
+    let saved = async {
+        let mut file = File::create(&location).await?;
+        file.write_all(&content).await?;
+        info!("Converting {} to png...", location);
+        convert_image_type(&prefix, extension, "png", challenge.allows_animation()).await
+    }.await;
+    if saved.is_err() {
+        let _ = remove_file(&location).await;
+    }
+    saved
+}
+
+/// Whether a submission's image file is present on the file system.
+pub async fn submission_exists(message_id: MessageId, challenge: Challenge, week_num: i64) -> ResT<bool> {
+    Ok(fs::try_exists(format!("{}.png", submission_prefix(message_id, challenge, week_num))).await?)
 }
 
 /// Remove a submission's image file from the file system
 pub async fn delete_submission(message_id: MessageId, challenge: Challenge, week_num: i64) -> Res {
-    let short_name = challenge.short_name();
-    info!(
-        "Removing file generation/images/{}/{}/{}.png",
-        short_name, week_num, message_id
-    );
-    remove_file(format!(
-        "generation/images/{short_name}/{week_num}/{message_id}.png"
-    ))
-    .await?;
+    let location = format!("{}.png", submission_prefix(message_id, challenge, week_num));
+    info!("Removing file {}", location);
+    remove_file(location).await?;
     Ok(())
 }
 
-/// Download a user's profile picture and save it to the right location.
+/// Make a string safe to use as a single file or directory name. Anything other than ASCII
+/// letters, digits, `-`, `_` and non-leading `.` is replaced by `_`, so the result can't contain
+/// a path separator or a null byte, and can't be `.`, `..` or empty.
+pub fn sanitize_path_component(name: &str) -> String {
+    let sanitized: String = name.chars().enumerate().map(|(i, c)| match c {
+        'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+        '.' if i > 0 => c,
+        _ => '_',
+    }).collect();
+    if sanitized.is_empty() { "_".to_owned() } else { sanitized }
+}
+
+/// Directory in which the submission images of a week are stored.
+fn submission_dir(challenge: Challenge, week_num: i64) -> String {
+    format!("generation/images/{}/{}", sanitize_path_component(&challenge.short_name()), week_num)
+}
+
+/// Path (without extension) at which the image of a submission is stored.
+fn submission_prefix(message_id: MessageId, challenge: Challenge, week_num: i64) -> String {
+    format!("{}/{}", submission_dir(challenge, week_num), sanitize_path_component(&message_id.to_string()))
+}
+
+/// Download a user's profile picture and save it to the right location. This does nothing if
+/// the stored file is already of the user's current avatar.
 pub async fn download_pfp(member: &Member) -> Res {
-    let response = reqwest::get(member.face()).await?;
-    let content = response.bytes().await?;
+    let face = member.face();
     let extension = "png";
     let user_id = member.user.id;
-    let prefix = format!("generation/images/pfp/{user_id}");
+    let prefix = format!("generation/images/pfp/{}", sanitize_path_component(&user_id.to_string()));
     let location = format!("{}.{}", prefix, extension);
+    // the avatar hash is recorded next to the image once it has been converted
+    let hash_location = format!("{}.hash", prefix);
+    let hash = avatar_hash(&face);
+    if file_mtime(&location).is_ok() && fs::read_to_string(&hash_location).await.is_ok_and(|stored| stored == hash) {
+        info!("Pfp file {} is already up to date", location);
+        return Ok(());
+    }
+
+    let response = reqwest::get(face).await?;
+    let content = response.bytes().await?;
     info!("Saving pfp file to {}", location);
     let mut file = File::create(&location).await?;
     file.write_all(&content).await?;
     info!("Converting {} to png...", location);
-    convert_image_type(&prefix, extension, "png").await?;
+    convert_image_type(&prefix, extension, "png", false).await?;
+    fs::write(&hash_location, hash).await?;
     Ok(())
 }
 
-/// Use `imagemagick` to convert an image to a different filetype
-pub async fn convert_image_type(prefix: &str, current_ext: &str, desired_ext: &str) -> Res {
-    let mut command = tokio::process::Command::new("convert");
-    // with the [0] in the first argument we ensure that a gif will have only the
-    // first frame taken.
-    command.arg(format!("{prefix}.{current_ext}[0]"));
-    command.arg(format!("{prefix}.{desired_ext}"));
+/// Extract the avatar hash from an avatar URL, i.e. the file name without extension or query.
+fn avatar_hash(url: &str) -> &str {
+    let path = url.split('?').next().unwrap_or(url);
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.split('.').next().unwrap_or(name)
+}
+
+static IMAGEMAGICK_BINARY: OnceLock<&'static str> = OnceLock::new();
+
+/// Where uploaded overrides for challenge images are kept.
+const IMAGE_OVERRIDE_DIR: &str = "generation/overrides";
+
+/// Where previously generated announcement images are kept for reuse.
+const IMAGE_CACHE_DIR: &str = "./generation/cache";
+
+/// Distinguishes image generations started within the same millisecond.
+static GENERATION_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Set once the bot starts shutting down, after which no more images are generated.
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Number of `generate.py` processes currently running.
+static RUNNING_GENERATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Notified when the bot shuts down, to kill any `generate.py` processes still running.
+static SHUTDOWN_NOTIFY: OnceLock<Notify> = OnceLock::new();
+
+/// How many lines from the end of `generate.py`'s stderr to include in errors.
+const GENERATION_ERROR_LINES: usize = 15;
+
+/// The `imagemagick` binary to use: `magick` if it is on the PATH, and otherwise the
+/// deprecated `convert` entrypoint of older versions. Resolved once and then cached.
+pub fn imagemagick_binary() -> &'static str {
+    IMAGEMAGICK_BINARY.get_or_init(|| {
+        let has_magick = std::env::var_os("PATH").map_or(false, |paths| {
+            std::env::split_paths(&paths).any(|dir| dir.join("magick").is_file())
+        });
+        if has_magick { "magick" } else { "convert" }
+    })
+}
+
+/// Use `imagemagick` to convert an image to a different filetype. Unless `keep_animation`
+/// is set, only the first frame of an animated image is kept.
+pub async fn convert_image_type(prefix: &str, current_ext: &str, desired_ext: &str, keep_animation: bool) -> Res {
+    let mut command = tokio::process::Command::new(imagemagick_binary());
+    if keep_animation {
+        command.arg(format!("{prefix}.{current_ext}"));
+        // imagemagick only writes every frame to a png if it is explicitly asked for an apng
+        if desired_ext == "png" { command.arg(format!("APNG:{prefix}.png")); }
+        else { command.arg(format!("{prefix}.{desired_ext}")); }
+    } else {
+        // with the [0] in the first argument we ensure that a gif will have only the
+        // first frame taken.
+        command.arg(format!("{prefix}.{current_ext}[0]"));
+        command.arg(format!("{prefix}.{desired_ext}"));
+    }
     command.kill_on_drop(true);
     info!("Running shell command {:?}", command);
     let res = command.spawn()?.wait().await?;
@@ -101,46 +226,359 @@ pub async fn convert_image_type(prefix: &str, current_ext: &str, desired_ext: &s
 pub async fn generate_challenge_image(challenge: Challenge, week_num: i64, options: ChallengeImageOptions,
         start_time: Timestamp, end_time: Timestamp, raw: bool) -> ResT<String> {
     
+    // An uploaded override for this week's image takes precedence over generating it ourselves.
+    let override_path = format!("{}.png", image_override_prefix(challenge, &options.suffix(), week_num));
+    if !raw && fs::try_exists(&override_path).await? {
+        info!("Using uploaded image override {}", override_path);
+        return Ok(override_path);
+    }
+
+    // Reuse an image generated earlier from the same inputs, if there is one.
+    let cache_path = if raw { None } else { image_cache_path(challenge, week_num, &options, start_time, end_time) };
+    if let Some(cache_path) = &cache_path {
+        if fs::try_exists(cache_path).await? {
+            info!("Using cached image {}", cache_path);
+            return Ok(cache_path.clone());
+        }
+    }
+
+    let path = render_challenge_image(challenge, week_num, options, start_time, end_time, raw).await?;
+    if let Some(cache_path) = &cache_path {
+        if let Err(e) = cache_image(&path, cache_path).await {
+            err!("Error caching image {}: {}", path, e);
+        }
+    }
+    Ok(path)
+}
+
+/// Get the path at which the image generated from the given inputs is cached. Only announcement
+/// images are cached, since poll images also depend on the submissions, which aren't part of the key.
+///
+/// The name contains the week number, so that old entries can be evicted, and a SHA-256 hash of
+/// everything that goes into the image. The hash must stay the same across builds, since the
+/// cache outlives the process.
+fn image_cache_path(challenge: Challenge, week_num: i64, options: &ChallengeImageOptions,
+        start_time: Timestamp, end_time: Timestamp) -> Option<String> {
+    let ChallengeImageOptions::Announcement { prompt_string, size_percentage } = options else { return None; };
+    let date = |time: Timestamp| time.0.map(format_image_date).map(|(date, iso)| format!("{date}\t{iso}")).unwrap_or_default();
+    let key = format!("{}\n{}\n{}\n{}\n{}", week_num, prompt_string, size_percentage, date(start_time), date(end_time));
+    let hash: String = Sha256::digest(key.as_bytes())[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    let name = sanitize_path_component(&format!("{}_{}_{}_{}", challenge.long_name(), options.suffix(), week_num, hash));
+    Some(format!("{}/{}.png", IMAGE_CACHE_DIR, name))
+}
+
+/// Copy a generated image into the cache. The copy is made under a temporary name and then
+/// renamed, so that a concurrent lookup never sees a partially written image.
+async fn cache_image(path: &str, cache_path: &str) -> Res {
+    fs::create_dir_all(IMAGE_CACHE_DIR).await?;
+    let temp_path = format!("{}.{}.tmp", cache_path, GENERATION_COUNTER.fetch_add(1, Ordering::Relaxed));
+    fs::copy(path, &temp_path).await?;
+    fs::rename(&temp_path, cache_path).await?;
+    Ok(())
+}
+
+/// Remove all cached images for a challenge, e.g. because its queue has changed.
+pub async fn clear_image_cache(challenge: Challenge) -> Res {
+    remove_cached_images(challenge, |_| true).await
+}
+
+/// Remove the cached images for weeks of a challenge before `first_week`, which won't be
+/// posted anymore. Entries that don't name a week, e.g. from older versions, are removed too.
+pub async fn evict_image_cache(challenge: Challenge, first_week: i64) -> Res {
+    remove_cached_images(challenge, |week_num| week_num.map_or(true, |week_num| week_num < first_week)).await
+}
+
+/// Remove the cached images for a challenge for whose week number `remove` returns true.
+async fn remove_cached_images(challenge: Challenge, remove: impl Fn(Option<i64>) -> bool) -> Res {
+    let mut entries = match fs::read_dir(IMAGE_CACHE_DIR).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let prefix = format!("{}_", challenge.long_name());
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(rest) = name.strip_prefix(&prefix) else { continue; };
+        // names are {challenge}_{suffix}_{week}_{hash}.png
+        let week_num = rest.split('_').nth(1).and_then(|week| week.parse::<i64>().ok());
+        if remove(week_num) {
+            remove_file(entry.path()).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Like [`generate_challenge_image`], but always runs `generate.py`, even if there is an
+/// uploaded override for the image.
+pub async fn render_challenge_image(challenge: Challenge, week_num: i64, options: ChallengeImageOptions,
+        start_time: Timestamp, end_time: Timestamp, raw: bool) -> ResT<String> {
     let name = format!("{}_{}", challenge.long_name(), options.suffix());
+
+    // Every invocation gets its own files, so concurrent generations can't clobber each other.
+    let job = sanitize_path_component(&format!("{}_{}_{}_{}", name, week_num, Utc::now().timestamp_millis(), GENERATION_COUNTER.fetch_add(1, Ordering::Relaxed)));
+
     let mut command = tokio::process::Command::new("./generate.py");
     command.arg("--verbose");
+    command.arg("--jobname");
+    command.arg(&job);
+    command.arg("--out");
+    command.arg(&job);
     command.arg("--week");
     command.arg(week_num.to_string());
+    let (start_date, start_date_iso) = format_image_date(start_time.0.ok_or("Challenge image has no start date")?);
+    let (end_date, end_date_iso) = format_image_date(end_time.0.ok_or("Challenge image has no end date")?);
     command.arg("--start_date");
-    command.arg(format!("{}",start_time.0.unwrap().format("%d/%m/%Y")));
+    command.arg(start_date);
+    command.arg("--start_date_iso");
+    command.arg(start_date_iso);
     command.arg("--end_date");
-    command.arg(format!("{}",end_time.0.unwrap().format("%d/%m/%Y")));
+    command.arg(end_date);
+    command.arg("--end_date_iso");
+    command.arg(end_date_iso);
     command.arg(&name);
+    command.args(image_options_args(options));
+    command.kill_on_drop(true);
+    command.current_dir("./generation");
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+
+    // Run it, retrying with exponential backoff if the script fails, since that
+    // may be a transient problem. Failing to start the script at all is not retried.
+    let attempts = config().generation_attempts;
+    let mut delay = config().generation_retry_delay;
+    for attempt in 1..=attempts {
+        info!("Running shell command {:?} (attempt {}/{})", command, attempt, attempts);
+        let output = match run_generation(&mut command).await {
+            Ok(output) => output,
+            Err(e) => { remove_generated_files(&job).await; return Err(e); }
+        };
+        if output.status.success() { metrics::increment(Metric::ImagesGenerated); break; }
+        metrics::increment(Metric::GenerationFailures);
+
+        // The full output is logged, but only the end of stderr, where the actual error
+        // usually is, goes into the returned error so that it stays readable.
+        info!("Output of failed image generation:\n{}", String::from_utf8_lossy(&output.stdout));
+        let stderr = output_tail(&output.stderr, GENERATION_ERROR_LINES);
+        if attempt == attempts {
+            remove_generated_files(&job).await;
+            return Err(format!("Failed to generate image ({}):\n{}", output.status, stderr).into());
+        }
+        err!("Failed to generate image ({}), retrying in {}s:\n{}", output.status, delay.as_secs(), stderr);
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+    Ok(if raw { format!("./generation/{job}.pdf") } else { Challenge::name_to_path(&job) })
+}
+
+/// The arguments to `generate.py` that depend on the type of image. Prompts and nicknames come
+/// from users, so they go after a `--`; otherwise one that starts with a dash would be parsed
+/// as an option rather than as text.
+fn image_options_args(options: ChallengeImageOptions) -> Vec<String> {
     match options {
-        ChallengeImageOptions::Announcement { prompt_string, size_percentage } => {
-            command.arg(&prompt_string);
-            command.arg("--size_percentage");
-            command.arg(size_percentage.to_string());
+        ChallengeImageOptions::Announcement { prompt_string, size_percentage }
+        | ChallengeImageOptions::Poll { prompt_string, size_percentage } => {
+            vec!["--size_percentage".to_owned(), size_percentage.to_string(), "--".to_owned(), prompt_string]
         }
-        ChallengeImageOptions::Poll { prompt_string, size_percentage} => {
-            command.arg(&prompt_string);
-            command.arg("--size_percentage");
-            command.arg(size_percentage.to_string());
+        ChallengeImageOptions::Winner { winner_nick, winner_id, submission_id, .. } => {
+            vec!["--".to_owned(), winner_nick, winner_id.to_string(), submission_id.to_string()]
         }
-        ChallengeImageOptions::Winner { position, winner_nick, winner_id, submission_id: submission } => {
-            command.arg(&winner_nick);
-            command.arg(winner_id.to_string());
-            command.arg(submission.to_string());
+    }
+}
+
+/// Run a `generate.py` command to completion, unless the bot shuts down first, in
+/// which case the process is killed.
+async fn run_generation(command: &mut tokio::process::Command) -> ResT<Output> {
+    // Register for the shutdown notification *before* checking whether we're shutting
+    // down, so that we can't miss a notification sent in between.
+    let shutdown = SHUTDOWN_NOTIFY.get_or_init(Notify::new).notified();
+    tokio::pin!(shutdown);
+    shutdown.as_mut().enable();
+    if SHUTTING_DOWN.load(Ordering::SeqCst) { return Err("Not generating image: shutting down".into()); }
+
+    let child = command.spawn().map_err(generation_spawn_error)?;
+    RUNNING_GENERATIONS.fetch_add(1, Ordering::SeqCst);
+    // If we're shut down, the child is dropped along with the `wait_with_output` future,
+    // and `kill_on_drop` takes care of killing it.
+    let output: ResT<Output> = select! {
+        output = child.wait_with_output() => output.map_err(|e| e.into()),
+        _ = shutdown => Err("Image generation aborted: shutting down".into()),
+    };
+    RUNNING_GENERATIONS.fetch_sub(1, Ordering::SeqCst);
+    output
+}
+
+/// Check that `generate.py` can be run at all, by asking it for its usage.
+pub async fn check_generation_script() -> Res {
+    const CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+    let status = tokio::time::timeout(CHECK_TIMEOUT, tokio::process::Command::new("./generate.py")
+        .arg("--help")
+        .current_dir("./generation")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .status()
+    ).await.map_err(|_| "generate.py did not respond in time")?.map_err(generation_spawn_error)?;
+    if !status.success() { return Err(format!("generate.py exited with {}", status).into()); }
+    Ok(())
+}
+
+/// Turn a failure to start `generate.py` into an error that says what to do about it. The
+/// raw error for a missing script or interpreter is just ‘No such file or directory’.
+fn generation_spawn_error(e: std::io::Error) -> Error {
+    match e.kind() {
+        std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied => format!(
+            "generate.py not found or not executable ({e}). Make sure generation/generate.py exists, \
+            is executable, and that its Python interpreter is installed."
+        ).into(),
+        _ => e.into(),
+    }
+}
+
+/// Check that `generate.py` exists and is executable, without running it. Only intended to
+/// be called by main(), to warn about a missing script before any image is needed.
+pub fn check_generation_script_installed() -> Res {
+    const PATH: &str = "./generation/generate.py";
+    let metadata = std::fs::metadata(PATH).map_err(|e| format!("{PATH} not found: {e}"))?;
+    if !metadata.is_file() || metadata.permissions().mode() & 0o111 == 0 {
+        return Err(format!("{PATH} is not an executable file").into());
+    }
+    Ok(())
+}
+
+/// Kill any `generate.py` processes that are still running and prevent new ones from
+/// being started. Only intended to be called by [`terminate()`](crate::core::terminate).
+pub async fn __glyfi_stop_generations() {
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+    SHUTDOWN_NOTIFY.get_or_init(Notify::new).notify_waiters();
+
+    // Give the generation tasks a moment to notice and drop their processes.
+    for _ in 0..100 {
+        if RUNNING_GENERATIONS.load(Ordering::SeqCst) == 0 { return; }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    err!("{} image generations still running after shutdown", RUNNING_GENERATIONS.load(Ordering::SeqCst));
+}
+
+/// The last `lines` lines of a subprocess's output.
+fn output_tail(output: &[u8], lines: usize) -> String {
+    let output = String::from_utf8_lossy(output);
+    let all_lines: Vec<&str> = output.trim_end().lines().collect();
+    all_lines[all_lines.len().saturating_sub(lines)..].join("\n")
+}
+
+/// Format a date for a challenge image in the configured `image_timezone`, returning both the date
+/// in the configured `image_date_format` and the date in ISO 8601 (`YYYY-MM-DD`) form.
+pub fn format_image_date(time: DateTime<Utc>) -> (String, String) {
+    format_date_in(time, config().image_timezone, &config().image_date_format)
+}
+
+/// [`format_image_date`], but in a given timezone and format.
+fn format_date_in(time: DateTime<Utc>, timezone: Tz, format: &str) -> (String, String) {
+    let local = time.with_timezone(&timezone);
+    (local.format(format).to_string(), local.format("%Y-%m-%d").to_string())
+}
+
+/// Read an image returned by [`generate_challenge_image`] into an attachment, then remove
+/// the files that were generated for it. Uploaded overrides and cached images are left in place.
+pub async fn take_challenge_image(path: &str) -> ResT<CreateAttachment> {
+    let attachment = CreateAttachment::path(path).await?;
+    if !path.starts_with(IMAGE_OVERRIDE_DIR) && !path.starts_with(IMAGE_CACHE_DIR) {
+        if let Some(job) = path.strip_prefix("./generation/").and_then(|p| p.rsplit_once('.')).map(|(job, _)| job) {
+            remove_generated_files(job).await;
         }
     }
-    command.kill_on_drop(true);
-    command.current_dir("./generation");
-    info!("Running shell command {:?}", command);
+    Ok(attachment)
+}
 
-    // Run it.
-    let res = command.spawn()?.wait().await?;
-    if !res.success() { return Err("Failed to generate image".into()); }
-    Ok(if raw { "./generation/weekly_challenges.pdf".to_owned() } else { Challenge::name_to_path(&name)} )
+/// Remove the output and intermediate files of a single run of `generate.py`.
+async fn remove_generated_files(job: &str) {
+    for extension in ["png", "pdf", "tex", "aux", "log"] {
+        let location = format!("./generation/{job}.{extension}");
+        if let Err(e) = remove_file(&location).await {
+            if e.kind() != std::io::ErrorKind::NotFound { err!("Failed to remove generated file {}: {}", location, e); }
+        }
+    }
+}
+
+/// Path (without extension) at which an uploaded override for the image of a given type
+/// (see [`ChallengeImageOptions::suffix`]) for a given week is stored.
+pub fn image_override_prefix(challenge: Challenge, suffix: &str, week_num: i64) -> String {
+    format!("{}/{}", IMAGE_OVERRIDE_DIR, sanitize_path_component(&format!("{}_{}_{}", challenge.long_name(), suffix, week_num)))
+}
+
+/// Save an uploaded image to be used instead of a generated challenge image for a given week,
+/// overwriting any previous override. Returns the path to the stored file.
+pub async fn save_image_override(attachment: &Attachment, challenge: Challenge, image_type: &UploadableImages, week_num: i64) -> ResT<String> {
+    let content = attachment.download().await?;
+    fs::create_dir_all(IMAGE_OVERRIDE_DIR).await?;
+    let prefix = image_override_prefix(challenge, &image_type.suffix(), week_num);
+    let location = format!("{prefix}.png");
+    info!("Saving image override to {}", location);
+    let mut file = File::create(&location).await?;
+    file.write_all(&content).await?;
+    info!("Converting {} to png...", location);
+    convert_image_type(&prefix, "png", "png", false).await?;
+    Ok(location)
+}
+
+/// Remove the uploaded overrides for a challenge's images for all weeks up to and including
+/// `last_week`, e.g. once they have been posted.
+pub async fn remove_image_overrides(challenge: Challenge, last_week: i64) -> Res {
+    let mut entries = match fs::read_dir(IMAGE_OVERRIDE_DIR).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let prefix = format!("{}_", challenge.long_name());
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let week_num = name.strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(".png"))
+            .and_then(|rest| rest.rsplit_once('_'))
+            .and_then(|(_, week)| week.parse::<i64>().ok());
+        if week_num.is_some_and(|week_num| week_num <= last_week) {
+            info!("Removing image override {}", name);
+            remove_file(entry.path()).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove the submission directories of a challenge for all weeks before `cutoff_week`. Returns the
+/// number of files and directories that were removed, or that would have been if `dry_run` is set.
+pub async fn prune_submission_images(challenge: Challenge, cutoff_week: i64, dry_run: bool) -> ResT<(usize, usize)> {
+    let (mut files, mut dirs) = (0, 0);
+    // nothing to prune if no submissions have been stored for this challenge yet
+    let mut entries = match fs::read_dir(format!("generation/images/{}", sanitize_path_component(&challenge.short_name()))).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((0, 0)),
+        Err(e) => return Err(e.into()),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        // anything that isn't a week's directory is left alone
+        let Some(week_num) = entry.file_name().to_str().and_then(|name| name.parse::<i64>().ok()) else { continue };
+        if week_num >= cutoff_week || !entry.file_type().await?.is_dir() { continue; }
+
+        // count everything below the directory before removing it
+        let mut pending = vec![entry.path()];
+        while let Some(dir) = pending.pop() {
+            dirs += 1;
+            let mut children = fs::read_dir(&dir).await?;
+            while let Some(child) = children.next_entry().await? {
+                if child.file_type().await?.is_dir() { pending.push(child.path()); } else { files += 1; }
+            }
+        }
+
+        if !dry_run {
+            info!("Removing submission directory {}", entry.path().display());
+            fs::remove_dir_all(entry.path()).await?;
+        }
+    }
+    Ok((files, dirs))
 }
 
 pub async fn initialise_submissions_directory(challenge: Challenge, week_num: i64) -> Res {
-    let short_name = challenge.short_name();
-    let dir = format!("generation/images/{short_name}/{week_num}");
+    let dir = submission_dir(challenge, week_num);
     fs::create_dir(&dir).await.or_else(|err| {
         if err.kind() == std::io::ErrorKind::AlreadyExists {
             Ok(())
@@ -148,4 +586,59 @@ pub async fn initialise_submissions_directory(challenge: Challenge, week_num: i6
             Err(err.into())
         }
     })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use chrono_tz::America::New_York;
+    use poise::serenity_prelude::UserId;
+    use crate::types::WinnerPosition;
+
+    #[test]
+    fn format_date_across_dst() {
+        let format = |y, m, d, h| format_date_in(Utc.with_ymd_and_hms(y, m, d, h, 30, 0).unwrap(), New_York, "%d/%m/%Y %H:%M");
+
+        // Clocks go forward at 07:00 UTC on 10 March 2024...
+        assert_eq!(format(2024, 3, 9, 4), ("08/03/2024 23:30".to_owned(), "2024-03-08".to_owned()));
+        assert_eq!(format(2024, 3, 11, 4), ("11/03/2024 00:30".to_owned(), "2024-03-11".to_owned()));
+
+        // ...and back at 06:00 UTC on 3 November 2024.
+        assert_eq!(format(2024, 11, 2, 4), ("02/11/2024 00:30".to_owned(), "2024-11-02".to_owned()));
+        assert_eq!(format(2024, 11, 4, 4), ("03/11/2024 23:30".to_owned(), "2024-11-03".to_owned()));
+    }
+
+    #[test]
+    fn user_text_is_passed_after_separator() {
+        let args = image_options_args(ChallengeImageOptions::Announcement { prompt_string: "--size_percentage 9999".to_owned(), size_percentage: 100 });
+        assert_eq!(args, ["--size_percentage", "100", "--", "--size_percentage 9999"]);
+
+        let args = image_options_args(ChallengeImageOptions::Winner { position: WinnerPosition::First,
+            winner_nick: "--week 1".to_owned(), winner_id: UserId::new(2), submission_id: MessageId::new(3) });
+        assert_eq!(args, ["--", "--week 1", "2", "3"]);
+    }
+
+    #[test]
+    fn sanitize_path_separators() {
+        assert_eq!(sanitize_path_component("a/b"), "a_b");
+        assert_eq!(sanitize_path_component("../../etc/passwd"), "_._.._etc_passwd");
+        assert_eq!(sanitize_path_component("a\\b"), "a_b");
+    }
+
+    #[test]
+    fn sanitize_special_names() {
+        assert_eq!(sanitize_path_component(""), "_");
+        assert_eq!(sanitize_path_component("."), "_");
+        assert_eq!(sanitize_path_component(".."), "_.");
+        assert_eq!(sanitize_path_component(".hidden"), "_hidden");
+        assert_eq!(sanitize_path_component("a\0b"), "a_b");
+        assert_eq!(sanitize_path_component("naïve name"), "na_ve_name");
+    }
+
+    #[test]
+    fn sanitize_keeps_safe_names() {
+        assert_eq!(sanitize_path_component("glyph_announcement_12"), "glyph_announcement_12");
+        assert_eq!(sanitize_path_component("image-1.png"), "image-1.png");
+    }
+}
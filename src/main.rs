@@ -2,33 +2,33 @@
 #![allow(deprecated)]
 
 mod commands;
+mod config;
 mod core;
 mod events;
 mod file;
+mod metrics;
 mod scheduling;
 mod server_data;
 mod sql;
 mod types;
 
-use crate::commands::{nickname, profile, queue, update};
+use crate::commands::{admin, current, history, leaderboard, nickname, poll, preview, profile, queue, stats, submissions, update, vote_summaries, week_info, withdraw};
 use crate::core::{log_command, terminate};
 use crate::events::GlyfiEvents;
-use crate::scheduling::schedule_loop;
-use crate::server_data::SERVER_ID;
+use crate::config::config;
 use clap::Parser;
 use commands::image;
 use poise::serenity_prelude::futures::TryFutureExt;
 use poise::serenity_prelude as ser;
-use server_data::TIME_GAP;
-use types::NULL_TIMESTAMP;
-use std::sync::Arc;
+use types::{RemovedSubmissionData, UserVoteStatusData, NULL_TIMESTAMP};
+use std::sync::{Arc, OnceLock};
 use tokio::try_join;
 
 /// Global context. Ugly, but this is the best way I can think
 /// of to support graceful shutdown on Ctrl+C etc.
-static mut __GLYFI_CONTEXT: Option<ser::Context> = None;
-static mut __GLYFI_FRAMEWORK: Option<Arc<ser::ShardManager>> = None;
-static mut __GLYFI_RUNTIME: Option<tokio::runtime::Handle> = None;
+static __GLYFI_CONTEXT: OnceLock<ser::Context> = OnceLock::new();
+static __GLYFI_FRAMEWORK: OnceLock<Arc<ser::ShardManager>> = OnceLock::new();
+static __GLYFI_RUNTIME: OnceLock<tokio::runtime::Handle> = OnceLock::new();
 
 /// User data.
 #[derive(Default, Debug)]
@@ -50,15 +50,15 @@ struct Args {
 }
 
 /// Only to be called by [`terminate()`].
-pub async unsafe fn __glyfi_terminate_bot() {
-    if let Some(fw) = __GLYFI_FRAMEWORK.as_ref() {
+pub async fn __glyfi_terminate_bot() {
+    if let Some(fw) = __GLYFI_FRAMEWORK.get() {
         fw.shutdown_all().await;
     }
 }
 
 /// This is called from a thread that is not part of the runtime.
-unsafe fn __glyfi_ctrlc_impl() {
-    let handle = __GLYFI_RUNTIME.as_ref().unwrap();
+fn __glyfi_ctrlc_impl() {
+    let handle = __GLYFI_RUNTIME.get().unwrap();
     let _guard = handle.enter();
     handle.block_on(terminate());
 }
@@ -68,14 +68,17 @@ async fn register_impl(
     http: impl AsRef<ser::Http>,
     framework: &poise::Framework<Data, Error>,
 ) -> Res {
-    info_sync!("Registering commands...");
-    poise::builtins::register_in_guild(http, &framework.options().commands, SERVER_ID).await?;
-    info_sync!("Commands registered.");
+    info!("Registering commands...");
+    poise::builtins::register_in_guild(http, &framework.options().commands, config().server_id).await?;
+    info!("Commands registered.");
     Ok(())
 }
 
 #[tokio::main]
 async fn main() {
+    core::init_logging();
+    config::__glyfi_load_config();
+
     // Register a panic hook to tear down the bot in case of an error;
     // this is so the bot restarts on error instead of hanging.
     let old_panic = std::panic::take_hook();
@@ -85,46 +88,63 @@ async fn main() {
     }));
 
     // Save runtime.
-    unsafe {
-        __GLYFI_RUNTIME = Some(tokio::runtime::Handle::current());
-    }
+    let _ = __GLYFI_RUNTIME.set(tokio::runtime::Handle::current());
 
     // Register the SIGINT handler.
     //
     // Do this *after* saving the runtime as the handler will
     // attempt to enter the runtime.
-    ctrlc::set_handler(|| unsafe { __glyfi_ctrlc_impl() })
+    ctrlc::set_handler(__glyfi_ctrlc_impl)
         .expect("Failed to register SIGINT handler");
 
     // Initialise the database.
-    unsafe {
-        sql::__glyfi_init_db().await;
+    sql::__glyfi_init_db().await;
+    core::__glyfi_load_maintenance_mode().await;
+
+    // Look up which imagemagick binary to use now rather than on first use.
+    info!("Using imagemagick binary `{}`", file::imagemagick_binary());
+
+    // Images can't be generated without the script, but everything else still works.
+    if let Err(e) = file::check_generation_script_installed() {
+        err!("{}; generating images will fail until this is fixed.", e);
     }
 
+    // Start the metrics server, if enabled.
+    metrics::spawn_server();
+
     let args = Args::parse();
     let fw = poise::Framework::builder()
         .options(poise::FrameworkOptions {
             pre_command: |ctx| Box::pin(async move { log_command(ctx).await; }),
             commands: vec![
+                admin(),
+                current(),
+                history(),
                 nickname(),
+                poll(),
+                preview(),
                 profile(),
+                leaderboard(),
                 queue(),
                 image(),
+                stats(),
+                submissions(),
                 update(),
+                vote_summaries(),
+                week_info(),
+                withdraw(),
             ],
             ..Default::default()
         })
 
         .setup(move |ctx, _, framework| {
-            unsafe {
-                __GLYFI_CONTEXT = Some(ctx.clone());
-                __GLYFI_FRAMEWORK = Some(framework.shard_manager().clone());
-            };
+            let _ = __GLYFI_CONTEXT.set(ctx.clone());
+            let _ = __GLYFI_FRAMEWORK.set(framework.shard_manager().clone());
 
             Box::pin(async move {
                 if args.register { register_impl(ctx, framework).await?; }
-                info_sync!("Setup done");
-                info_sync!("\x1b[1;33mRemember to double-check command permissions before deploying!\x1b[m");
+                info!("Setup done");
+                info!("\x1b[1;33mRemember to double-check command permissions before deploying!\x1b[m");
                 Ok(Default::default())
             })
         })
@@ -135,19 +155,24 @@ async fn main() {
         use types::{Challenge, WeekInfo};
         use sql::{insert_or_modify_week, set_current_week_num};
         use chrono::{DateTime, Utc};
-        for challenge in [Challenge::Glyph, Challenge::Ambigram].into_iter() {
+        for challenge in Challenge::all() {
             let current_time = Utc::now();
             insert_or_modify_week(WeekInfo { challenge, week_num: 0, prompt_string: "A".to_owned(), size_percentage: 100, target_start_time: current_time.into(),
-                target_end_time: (current_time + challenge.default_duration() - TIME_GAP).into(), actual_start_time: current_time.into(), 
-                actual_end_time: NULL_TIMESTAMP, is_special: false, num_subs: 0, poll_message_id: None.into(), second_poll_message_id: None.into() })
+                target_end_time: (current_time + challenge.default_duration() - config().time_gap).into(), actual_start_time: current_time.into(), 
+                actual_end_time: NULL_TIMESTAMP, is_special: false, num_subs: 0, poll_message_ids: Default::default(),
+                target_voting_end_time: NULL_TIMESTAMP, actual_voting_end_time: NULL_TIMESTAMP })
                 .await.map_err(|e| println!("Error initialising dummy challenge: {}", e));
             set_current_week_num(challenge, 0).await;
         }
     }
+    // The shared state is created here rather than in `ready`, which runs again on every
+    // reconnect and would throw away whatever is in progress.
     let mut client =
-        ser::ClientBuilder::new(server_data::DISCORD_BOT_TOKEN, ser::GatewayIntents::all())
+        ser::ClientBuilder::new(config::discord_bot_token(), ser::GatewayIntents::all())
             .framework(fw)
             .event_handler(GlyfiEvents)
+            .type_map_insert::<UserVoteStatusData>(Default::default())
+            .type_map_insert::<RemovedSubmissionData>(Default::default())
             .await
             .unwrap();
         
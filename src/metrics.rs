@@ -0,0 +1,110 @@
+//! Prometheus metrics. These are only collected and served if the bot is built
+//! with the `metrics` feature; otherwise, everything in here does nothing.
+
+/// Things we keep count of.
+#[derive(Copy, Clone, Debug)]
+pub enum Metric {
+    SubmissionsRegistered,
+    VotesCast,
+    ImagesGenerated,
+    SchedulerRuns,
+    GenerationFailures,
+}
+
+/// Increment the counter for a metric.
+pub fn increment(metric: Metric) {
+    #[cfg(feature = "metrics")]
+    imp::counter(metric).inc();
+    #[cfg(not(feature = "metrics"))]
+    let _ = metric;
+}
+
+/// Start serving the metrics over HTTP on the configured `metrics_port` in the background.
+pub fn spawn_server() {
+    #[cfg(feature = "metrics")]
+    tokio::spawn(imp::serve());
+}
+
+#[cfg(feature = "metrics")]
+mod imp {
+    use super::Metric;
+    use crate::config::config;
+    use crate::{err, info};
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use prometheus::{Encoder, IntCounter, Registry, TextEncoder};
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::OnceLock;
+
+    impl Metric {
+        const ALL: [Metric; 5] = [
+            Metric::SubmissionsRegistered,
+            Metric::VotesCast,
+            Metric::ImagesGenerated,
+            Metric::SchedulerRuns,
+            Metric::GenerationFailures,
+        ];
+
+        fn name(self) -> &'static str {
+            match self {
+                Metric::SubmissionsRegistered => "glyfi_submissions_registered_total",
+                Metric::VotesCast => "glyfi_votes_cast_total",
+                Metric::ImagesGenerated => "glyfi_images_generated_total",
+                Metric::SchedulerRuns => "glyfi_scheduler_runs_total",
+                Metric::GenerationFailures => "glyfi_generation_failures_total",
+            }
+        }
+
+        fn help(self) -> &'static str {
+            match self {
+                Metric::SubmissionsRegistered => "Number of submissions registered.",
+                Metric::VotesCast => "Number of votes added or removed.",
+                Metric::ImagesGenerated => "Number of challenge images generated.",
+                Metric::SchedulerRuns => "Number of times the scheduler has run.",
+                Metric::GenerationFailures => "Number of failed attempts at generating a challenge image.",
+            }
+        }
+    }
+
+    /// The registry, and a counter for each metric, in the order of [`Metric::ALL`].
+    static COUNTERS: OnceLock<(Registry, Vec<IntCounter>)> = OnceLock::new();
+
+    fn counters() -> &'static (Registry, Vec<IntCounter>) {
+        COUNTERS.get_or_init(|| {
+            let registry = Registry::new();
+            let counters = Metric::ALL.iter().map(|metric| {
+                // The names are fixed and distinct, so neither of these can fail.
+                let counter = IntCounter::new(metric.name(), metric.help()).unwrap();
+                registry.register(Box::new(counter.clone())).unwrap();
+                counter
+            }).collect();
+            (registry, counters)
+        })
+    }
+
+    pub fn counter(metric: Metric) -> &'static IntCounter {
+        &counters().1[metric as usize]
+    }
+
+    async fn handle(_: Request<Body>) -> Result<Response<Body>, Infallible> {
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&counters().0.gather(), &mut buffer) {
+            err!("Failed to encode metrics: {}", e);
+        }
+        Ok(Response::new(Body::from(buffer)))
+    }
+
+    pub async fn serve() {
+        let addr = SocketAddr::from(([0, 0, 0, 0], config().metrics_port));
+        let server = match Server::try_bind(&addr) {
+            Ok(server) => server,
+            Err(e) => { err!("Failed to bind metrics server to {}: {}", addr, e); return; }
+        };
+        info!("Serving metrics on {}", addr);
+        let make_service = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle)) });
+        if let Err(e) = server.serve(make_service).await {
+            err!("Metrics server failed: {}", e);
+        }
+    }
+}
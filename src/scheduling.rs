@@ -1,129 +1,472 @@
-use poise::serenity_prelude::{ButtonStyle, Context, CreateAttachment, CreateButton, CreateEmbed, CreateMessage, GuildId, MessageId};
-use tokio::time;
-use chrono::Utc;
+use poise::serenity_prelude::{ButtonStyle, Context, CreateAllowedMentions, CreateAttachment, CreateButton, CreateEmbed, CreateMessage, GuildId, MessageId};
+use std::sync::{atomic::{AtomicI64, Ordering}, OnceLock};
+use tokio::{sync::Mutex, time, try_join};
+use poise::ChoiceParameter;
+use tracing::instrument;
+use chrono::{DateTime, Duration, Utc};
 
-use crate::{err, file::{delete_submission, generate_challenge_image, initialise_submissions_directory}, info, server_data::{format_ambi_announcement_spiel, format_glyph_announcement_spiel, format_poll_spiel, EMPTY_MESSAGE, SERVER_ID, STATUS_UPDATE_CHANNEL_ID, TIME_GAP, VOTING_EMOJI_SEQUENCE}, sql::{delete_prompt, deregister_submission, end_week, get_current_week_num, get_prompt_data, get_submissions, get_week_info, initialise_week, rollover_week}, types::{Timestamp, NULL_TIMESTAMP}, Res};
+use crate::{err, file::{delete_submission, evict_image_cache, generate_challenge_image, take_challenge_image, initialise_submissions_directory, remove_image_overrides}, info, config::config, server_data::{format_poll_spiel, EMPTY_MESSAGE, VOTING_EMOJI_SEQUENCE}, sql::{delete_prompt, delete_prompt_by_id, deregister_submission, end_voting, end_week, get_announcement_message, get_current_week_num, get_next_prompt, get_submissions, get_vote_summary_users, get_votes, get_week_info, get_week_prompt_id, get_weeks_with_voting_due, initialise_week, insert_or_modify_week, open_voting, rank_winners, reminder_sent, rollover_week, set_announcement_message, set_reminder_sent, tally_week}, types::{Timestamp, WeekInfo, NULL_TIMESTAMP}, Error, Res, ResT};
+use crate::core::maintenance_mode;
+use crate::metrics::{self, Metric};
 use crate::types::{Challenge, ChallengeImageOptions::*};
 
+/// Discord allows at most 5 rows of 5 buttons per message.
+const POLL_BUTTONS_PER_MESSAGE: usize = 25;
+
+/// Held while a challenge is being advanced, so a manual rollover can't race the scheduler.
+static ADVANCE_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+
+/// Held for the whole of a scheduler run. Runs that start while another is still going
+/// are skipped rather than queued, so a slow run can never lead to things being posted twice.
+static SCHEDULE_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+
+/// Unix timestamp of the last scheduler run that completed without errors, or 0 if there wasn't one.
+static LAST_SCHEDULER_RUN: AtomicI64 = AtomicI64::new(0);
+
 pub async fn schedule_loop(ctx: &Context) -> Res {
-    for challenge in [Challenge::Glyph, Challenge::Ambigram].into_iter() {
+    let Ok(_guard) = SCHEDULE_LOCK.get_or_init(Default::default).try_lock() else {
+        info!("Previous scheduler run is still going; skipping this one.");
+        return Ok(());
+    };
+    if maintenance_mode() {
+        info!("Maintenance mode is on; skipping scheduler run.");
+        return Ok(());
+    }
+    metrics::increment(Metric::SchedulerRuns);
+    for challenge in Challenge::all() {
         info!("Checking status of {} challenge...", challenge.short_name());
-        let mut current_week_num = get_current_week_num(challenge).await?;
-        let current_week_info = get_week_info(current_week_num, challenge).await?;
-        let actual_end_time = current_week_info.actual_end_time;
-        let current_time = Utc::now();
-        if let Timestamp(Some(t)) = actual_end_time {
-            Some(current_time > t).filter(|_| true).ok_or("Unexpected state: end time of current week set in the future")?;
-            //we've already ended the challenge but haven't started the next one
-            if let Ok(next_week_data) = get_week_info(current_week_num + 1, challenge).await {
-                //next week has already been initialised; now we're just waiting for it to begin
-                if current_time > next_week_data.target_start_time.0.unwrap() {
-                    info!("Rolling over week for challenge {}. New prompt: {:?}", challenge.short_name(), next_week_data.prompt_string);
-
-                    let next_prompt_string = next_week_data.prompt_string;
-                    let target_start_time = next_week_data.target_start_time;
-                    let target_end_time = next_week_data.target_end_time;
-                    let target_timestamp = target_end_time.0.unwrap().timestamp();
-                    let full_discord_timestamp = format!("<t:{}:F>", target_timestamp);
-                    let relative_discord_timestamp = format!("<t:{}:R>", target_timestamp);
-            
-                    // get all the files
-                    // it's pretty important that we do this before posting anything, since otherwise we could
-                    // fail halfway through and end up only posting one file, and then we would end up posting
-                    // that file over and over again as the database is never updated
-                    let announcement_attachment = CreateAttachment::path(
-                        generate_challenge_image(challenge, current_week_num + 1, 
-                            Announcement { prompt_string: next_prompt_string.clone(),
-                            size_percentage: next_week_data.size_percentage }, 
-                            target_start_time, target_end_time, false
-                        ).await?
-                    ).await?;
-        
-                    let poll_attachment = CreateAttachment::path(
-                        generate_challenge_image(challenge, current_week_num, Poll { prompt_string: current_week_info.prompt_string, 
-                            size_percentage: current_week_info.size_percentage },
-                            current_week_info.target_start_time, current_week_info.target_end_time, false
-                        ).await?
-                    ).await?;
-        
-                    // post everything
-                    challenge.announcement_channel().send_message(&ctx, CreateMessage::new()
-                        .content( match challenge {
-                            Challenge::Glyph => format_glyph_announcement_spiel(current_week_num + 1, &next_prompt_string, 
-                                &full_discord_timestamp, &relative_discord_timestamp),
-                            Challenge::Ambigram => format_ambi_announcement_spiel(current_week_num + 1, &next_prompt_string, 
-                                &full_discord_timestamp, &relative_discord_timestamp),
-                        })
-                        .add_file(announcement_attachment)
-                    ).await?;
-        
-                    let mut poll_message_builder = CreateMessage::new()
-                        .content(format_poll_spiel(&full_discord_timestamp, &relative_discord_timestamp))
-                        .add_file(poll_attachment);
-        
-                    let mut first_numsubs = get_submissions(challenge, current_week_num).await?.len();
-                    let mut second_numsubs = 0;
-                    let mut second_poll_message_id: Option<MessageId> = None;
-        
-                    if first_numsubs > 25 {
-                        // we are just going to assume there are not >50 subs so both of these are at most 25
-                        second_numsubs = first_numsubs - 25;
-                        first_numsubs = 25;
-                    }
-        
-                    info!("There are {} + {} submissions for challenge {}.", first_numsubs, second_numsubs, challenge.short_name());
-        
-                    let prefix = format!("{}{:04}", challenge.one_char_name(), current_week_num);
-                    for (idx, emoji) in VOTING_EMOJI_SEQUENCE.iter().enumerate().take(first_numsubs) {
-                        poll_message_builder = poll_message_builder
-                            .button(CreateButton::new(format!("{}-{:03}", prefix, idx))
-                            .emoji(*emoji).style(ButtonStyle::Primary));
-                    }
-                    let poll_message = challenge.announcement_channel().send_message(&ctx, poll_message_builder).await?;
-        
-                    if second_numsubs > 0 {
-                        let mut second_poll_message_builder = CreateMessage::new().content(EMPTY_MESSAGE);
-                        for (idx, emoji) in VOTING_EMOJI_SEQUENCE.iter().enumerate().skip(first_numsubs).take(second_numsubs) {
-                            second_poll_message_builder = second_poll_message_builder
-                                .button(CreateButton::new(format!("{}-{:03}", prefix, idx))
-                                .emoji(*emoji).style(ButtonStyle::Primary));
-                        }
-                        let second_poll_message = challenge.announcement_channel()
-                            .send_message(&ctx, second_poll_message_builder).await?;
-                        second_poll_message_id = Some(second_poll_message.id);
-                    }
-        
-                    info!("Rolling over database...");
-                    rollover_week(challenge, current_week_num, Utc::now().into(), (first_numsubs + second_numsubs) as i64,
-                     poll_message.id, second_poll_message_id).await?;
-                    
-                    info!("Removing prompt from the database...");
-                    delete_prompt(challenge, 1).await?;
-        
-                    info!("Initialising file system for upcoming week...");
-                    initialise_submissions_directory(challenge, current_week_num + 1).await?;
-                    
-                    info!("Done rolling over week!");
-                }
-            } else if let Ok(next_prompt) = get_prompt_data(challenge, 1).await {
-                //we have a prompt to initialise next week
-                let next_target_start_time = current_week_info.target_end_time + TIME_GAP;;
-                let next_target_end_time = next_target_start_time + challenge.default_duration() 
-                    * next_prompt.custom_duration.unwrap_or(1) as i32 - TIME_GAP;
-                let week_num = current_week_num + 1;
-                info!("Initialising next week for challenge {}");
-                initialise_week(challenge, week_num, &next_prompt, next_target_start_time, next_target_end_time).await?;
-            } else {
-                //we need a prompt but don't have one
-                info!("No prompt to initialise next {} challenge.", challenge.short_name());
+        advance_challenge(ctx, challenge, false).await?;
+    }
+    LAST_SCHEDULER_RUN.store(Utc::now().timestamp(), Ordering::Relaxed);
+    Ok(())
+}
+
+/// When the scheduler last ran without errors.
+pub fn last_scheduler_run() -> Timestamp {
+    match LAST_SCHEDULER_RUN.load(Ordering::Relaxed) {
+        0 => NULL_TIMESTAMP,
+        t => t.try_into().unwrap_or(NULL_TIMESTAMP),
+    }
+}
+
+/// Perform whatever action is due for a challenge: concluding the voting on a week, ending the current
+/// week, opening voting on it, initialising the next one, or rolling over to it. Returns a description
+/// of what was done. If `dry_run` is set, nothing is posted or written, and the description is of what
+/// would have been done instead.
+///
+/// Special weeks only affect what is posted when rolling over from week N to week N+1; the database
+/// is advanced in exactly the same way regardless:
+///
+/// - The announcement for week N+1 is not posted if week N+1 is special.
+/// - The poll for week N is not posted if week N is special. Its `poll_message_ids` are then left
+///   empty, so no votes can be cast for it.
+///
+/// If `voting_duration` is configured, the poll for week N is instead posted as soon as week N ends,
+/// and voting on it is concluded once that long has passed rather than when week N+1 ends.
+#[instrument(name = "schedule", skip(ctx, challenge), fields(challenge = challenge.short_name()))]
+pub async fn advance_challenge(ctx: &Context, challenge: Challenge, dry_run: bool) -> ResT<String> {
+    let _guard = ADVANCE_LOCK.get_or_init(Default::default).lock().await;
+    let current_week_num = get_current_week_num(challenge).await?;
+    let current_week_info = get_week_info(current_week_num, challenge).await?;
+    let current_time = Utc::now();
+
+    // voting windows don't line up with weeks, so deal with any that have closed first
+    if let Some(&week_num) = get_weeks_with_voting_due(challenge, current_time.into()).await?.first() {
+        if dry_run {
+            return Ok(format!("Would conclude voting for week {}.", week_num));
+        }
+        info!("Concluding voting for week {} of challenge {}", week_num, challenge.short_name());
+        end_voting(challenge, week_num, current_time.into()).await?;
+        send_vote_summaries(ctx, challenge, week_num).await?;
+        return Ok(format!("Concluded voting for week {}.", week_num));
+    }
+
+    if has_ended(&current_week_info, current_time)? {
+        //we've already ended the challenge but haven't started the next one
+        if config().voting_duration.is_some() && current_week_info.target_voting_end_time == NULL_TIMESTAMP {
+            //voting should have opened when the week ended, but didn't
+            if dry_run {
+                return Ok(format!("Would open voting for week {}.", current_week_num));
+            }
+            start_voting(ctx, challenge, current_week_info).await
+        } else if let Ok(next_week_data) = get_week_info(current_week_num + 1, challenge).await {
+            //next week has already been initialised; now we're just waiting for it to begin
+            let next_target_start_time = next_week_data.target_start_time.0.ok_or("Unexpected state: next week has no start time")?;
+            if current_time <= next_target_start_time {
+                return Ok(format!("Waiting for week {} to begin {}.", current_week_num + 1, next_week_data.target_start_time.to_discord('R')));
+            }
+            if dry_run {
+                return Ok(format!("Would roll over to week {} with prompt ‘{}’.", current_week_num + 1, next_week_data.prompt_string));
             }
-        } else if current_time > current_week_info.target_end_time.0.unwrap() {
-            info!("Ending the current week for challenge {}", challenge.short_name());
-            end_week(challenge, current_week_num, Utc::now().into()).await?;
-            remove_absent_user_submissions(ctx, challenge, current_week_num, SERVER_ID).await?;
+            roll_over(ctx, challenge, current_week_num, current_week_info, next_week_data).await
+        } else if let Ok((_, prompt_id, next_prompt)) = get_next_prompt(challenge).await {
+            //we have a prompt to initialise next week
+            let next_target_start_time = next_prompt.start_time_after(current_week_info.target_end_time);
+            let next_target_end_time = next_target_start_time + challenge.default_duration() 
+                * next_prompt.custom_duration.unwrap_or(1) as i32 - config().time_gap;
+            let week_num = current_week_num + 1;
+            if dry_run {
+                return Ok(format!("Would initialise week {} with prompt ‘{}’.", week_num, next_prompt.prompt_string));
+            }
+            info!("Initialising next week for challenge {}", challenge.short_name());
+            initialise_week(challenge, week_num, prompt_id, &next_prompt, next_target_start_time, next_target_end_time).await?;
+            Ok(format!("Initialised week {}.", week_num))
         } else {
-            info!("No action needed for challenge {}", challenge.short_name());
+            //we need a prompt but don't have one
+            info!("No prompt to initialise next {} challenge.", challenge.short_name());
+            Ok(format!("No prompt in the queue to initialise week {}.", current_week_num + 1))
+        }
+    } else if current_time > current_week_info.target_end_time.0.ok_or("Unexpected state: current week has no end time")? {
+        if dry_run {
+            return Ok(format!("Would end week {}.", current_week_num));
+        }
+        info!("Ending the current week for challenge {}", challenge.short_name());
+        let concluded = end_week(challenge, current_week_num, Utc::now().into()).await?;
+        remove_absent_user_submissions(ctx, challenge, current_week_num, config().server_id).await?;
+        if concluded { send_vote_summaries(ctx, challenge, current_week_num - 1).await?; }
+        if config().voting_duration.is_some() {
+            let opened = start_voting(ctx, challenge, get_week_info(current_week_num, challenge).await?).await?;
+            return Ok(format!("Ended week {}. {}", current_week_num, opened));
+        }
+        Ok(format!("Ended week {}.", current_week_num))
+    } else if reminder_due(challenge, &current_week_info).await? {
+        if dry_run {
+            return Ok(format!("Would post the deadline reminder for week {}.", current_week_num));
+        }
+        send_reminder(ctx, challenge, &current_week_info).await?;
+        Ok(format!("Posted the deadline reminder for week {}.", current_week_num))
+    } else {
+        info!("No action needed for challenge {}", challenge.short_name());
+        Ok(format!("No action needed; week {} ends {}.", current_week_num, current_week_info.target_end_time.to_discord('R')))
+    }
+}
+
+/// Whether a week has ended by `current_time`. A week that claims to have ended in the
+/// future is an inconsistency that we refuse to act on.
+fn has_ended(week_info: &WeekInfo, current_time: DateTime<Utc>) -> ResT<bool> {
+    match week_info.actual_end_time.0 {
+        Some(t) if t > current_time => {
+            err!("Week {} of challenge {} ended at {}, which is in the future", week_info.week_num, week_info.challenge.short_name(), t);
+            Err("Unexpected state: end time of current week set in the future".into())
+        }
+        Some(_) => Ok(true),
+        None => Ok(false),
+    }
+}
+
+/// Whether the deadline reminder for a running week should be posted now. Special weeks
+/// aren't announced publicly, so they don't get a reminder either.
+async fn reminder_due(challenge: Challenge, week_info: &WeekInfo) -> ResT<bool> {
+    let Some(lead_time) = challenge.reminder_lead_time() else { return Ok(false) };
+    let Some(target_end_time) = week_info.target_end_time.0 else { return Ok(false) };
+    if week_info.is_special || Utc::now() < target_end_time - lead_time { return Ok(false); }
+    Ok(!reminder_sent(challenge, week_info.week_num).await?)
+}
+
+/// Remind participants in the submission channel that the current week is about to end.
+///
+/// The week is marked as reminded before posting, so a failure can at worst cost us the
+/// reminder rather than spam the channel with it on every scheduler run.
+async fn send_reminder(ctx: &Context, challenge: Challenge, week_info: &WeekInfo) -> Res {
+    info!("Posting deadline reminder for week {} of challenge {}", week_info.week_num, challenge.short_name());
+    set_reminder_sent(challenge, week_info.week_num).await?;
+    let content = format!(
+        "Reminder: submissions for this week’s {} challenge, ‘{}’, close {}!",
+        challenge.long_name(),
+        week_info.prompt_string,
+        week_info.target_end_time.to_discord('R')
+    );
+    challenge.submission_channel().send_message(ctx, CreateMessage::new().content(content)).await?;
+    Ok(())
+}
+
+/// Post the announcement for week N+1 and the poll for week N, then advance the database to week N+1.
+/// Week N must already have ended and week N+1 must already have been initialised. The poll is not
+/// posted again if voting on week N already opened when it ended.
+///
+/// Each message is recorded in the database as soon as it has been posted, and the database is only
+/// advanced once everything has been posted. If posting fails partway through, the next attempt
+/// therefore only posts whatever is still missing rather than repeating the announcement.
+async fn roll_over(ctx: &Context, challenge: Challenge, current_week_num: i64, current_week_info: WeekInfo, next_week_data: WeekInfo) -> ResT<String> {
+    info!("Rolling over week for challenge {}. New prompt: {:?}", challenge.short_name(), next_week_data.prompt_string);
+
+    let next_prompt_string = next_week_data.prompt_string;
+    let target_start_time = next_week_data.target_start_time;
+    let target_end_time = next_week_data.target_end_time;
+    let target_timestamp = target_end_time.0.unwrap().timestamp();
+    let full_discord_timestamp = format!("<t:{}:F>", target_timestamp);
+    let relative_discord_timestamp = format!("<t:{}:R>", target_timestamp);
+    let poll_posted = current_week_info.target_voting_end_time != NULL_TIMESTAMP || !current_week_info.poll_message_ids.0.is_empty();
+    let announced = get_announcement_message(challenge, current_week_num + 1).await?.is_some();
+
+    // get all the files
+    // it's pretty important that we do this before posting anything, since otherwise we could
+    // fail halfway through and end up only posting one file, and then we would end up posting
+    // that file over and over again as the database is never updated. The images are independent
+    // of each other, so we generate them concurrently; if either fails, nothing is posted.
+    let announcement_future = async {
+        if next_week_data.is_special || announced { return Ok::<_, Error>(None); }
+        Ok(Some(take_challenge_image(&
+            generate_challenge_image(challenge, current_week_num + 1, 
+                Announcement { prompt_string: next_prompt_string.clone(),
+                size_percentage: next_week_data.size_percentage }, 
+                target_start_time, target_end_time, false
+            ).await?
+        ).await?))
+    };
+
+    let poll_future = async {
+        if poll_posted { return Ok::<_, Error>(None); }
+        generate_poll_image(challenge, &current_week_info).await
+    };
+
+    let (announcement_attachment, poll_attachment) = try_join!(announcement_future, poll_future)?;
+
+    // post everything
+    if let Some(announcement_attachment) = announcement_attachment {
+        let spiel = challenge.format_announcement_spiel(current_week_num + 1, &next_prompt_string,
+            &full_discord_timestamp, &relative_discord_timestamp);
+        let mut announcement_builder = CreateMessage::new().add_file(announcement_attachment);
+        // only ping the configured role, even if the spiel happens to mention anything else
+        announcement_builder = match challenge.announcement_role() {
+            Some(role) => announcement_builder
+                .content(format!("<@&{}> {}", role, spiel))
+                .allowed_mentions(CreateAllowedMentions::new().roles(vec![role])),
+            None => announcement_builder.content(spiel),
+        };
+        let announcement = challenge.announcement_channel().send_message(&ctx, announcement_builder).await?;
+        set_announcement_message(challenge, current_week_num + 1, announcement.id).await?;
+    } else if announced {
+        info!("Week {} has already been announced; not posting an announcement.", current_week_num + 1);
+    } else {
+        info!("Week {} is special; not posting an announcement.", current_week_num + 1);
+    }
+
+    let (poll_message_ids, target_voting_end_time) = if poll_posted {
+        info!("The poll for week {} has already been posted; not posting it again.", current_week_num);
+        (current_week_info.poll_message_ids.0, current_week_info.target_voting_end_time)
+    } else {
+        // without a voting window of its own, voting lasts until the next week ends
+        let target_voting_end_time = config().voting_duration.map(|d| Timestamp::from(Utc::now() + d)).unwrap_or(NULL_TIMESTAMP);
+        let poll_end_time = if target_voting_end_time == NULL_TIMESTAMP { target_end_time } else { target_voting_end_time };
+
+        let numsubs = get_submissions(challenge, current_week_num).await?.len();
+        info!("There are {} submissions for challenge {}.", numsubs, challenge.short_name());
+
+        let poll_message_ids = match poll_attachment {
+            Some(poll_attachment) => post_poll(ctx, challenge, current_week_num, numsubs, poll_attachment, poll_end_time).await?,
+            None => { info!("Week {} is special; not posting a poll.", current_week_num); Vec::new() }
+        };
+        open_voting(challenge, current_week_num, poll_message_ids.clone(), target_voting_end_time).await?;
+        (poll_message_ids, target_voting_end_time)
+    };
+
+    info!("Rolling over database...");
+    rollover_week(challenge, current_week_num, Utc::now().into(), poll_message_ids, target_voting_end_time).await?;
+    
+    // remove the prompt the week was initialised from, even if the queue has changed since;
+    // weeks from before this was recorded still take the first prompt that isn't skipped
+    info!("Removing prompt from the database...");
+    match get_week_prompt_id(challenge, current_week_num + 1).await? {
+        Some(prompt_id) => if !delete_prompt_by_id(challenge, prompt_id).await? {
+            info!("Prompt {} of week {} is no longer in the queue.", prompt_id, current_week_num + 1);
+        },
+        None => { delete_prompt(challenge, get_next_prompt(challenge).await?.0).await?; }
+    }
+
+    info!("Initialising file system for upcoming week...");
+    initialise_submissions_directory(challenge, current_week_num + 1).await?;
+
+    info!("Removing image overrides...");
+    remove_image_overrides(challenge, current_week_num + 1).await?;
+
+    info!("Evicting old images from the cache...");
+    evict_image_cache(challenge, current_week_num + 1).await?;
+    
+    info!("Done rolling over week!");
+    Ok(format!("Rolled over to week {}.", current_week_num + 1))
+}
+
+/// Post the poll for a week that has just ended, and open voting on it for the configured
+/// `voting_duration`. Nothing is posted if the week is special.
+async fn start_voting(ctx: &Context, challenge: Challenge, week_info: WeekInfo) -> ResT<String> {
+    let week_num = week_info.week_num;
+    let voting_duration = config().voting_duration.ok_or("No voting duration is configured")?;
+    let target_voting_end_time: Timestamp = (Utc::now() + voting_duration).into();
+
+    let numsubs = get_submissions(challenge, week_num).await?.len();
+    info!("Opening voting on {} submissions for week {} of challenge {}", numsubs, week_num, challenge.short_name());
+    let poll_message_ids = match generate_poll_image(challenge, &week_info).await? {
+        Some(poll_attachment) => post_poll(ctx, challenge, week_num, numsubs, poll_attachment, target_voting_end_time).await?,
+        None => { info!("Week {} is special; not posting a poll.", week_num); Vec::new() }
+    };
+
+    open_voting(challenge, week_num, poll_message_ids, target_voting_end_time).await?;
+    Ok(format!("Opened voting for week {} until {}.", week_num, target_voting_end_time.to_discord('f')))
+}
+
+/// Generate the poll image for a week, or nothing if the week is special.
+async fn generate_poll_image(challenge: Challenge, week_info: &WeekInfo) -> ResT<Option<CreateAttachment>> {
+    if week_info.is_special { return Ok(None); }
+    Ok(Some(take_challenge_image(&
+        generate_challenge_image(challenge, week_info.week_num, Poll { prompt_string: week_info.prompt_string.clone(), 
+            size_percentage: week_info.size_percentage },
+            week_info.target_start_time, week_info.target_end_time, false
+        ).await?
+    ).await?))
+}
+
+/// Post the poll for a week, closing at `voting_end_time`. Returns the ids of the poll messages.
+/// If any of them fails to post, the ones that did are deleted again, so that a half-posted
+/// poll doesn't linger in the channel when the poll is retried.
+async fn post_poll(ctx: &Context, challenge: Challenge, week_num: i64, numsubs: usize, poll_attachment: CreateAttachment, voting_end_time: Timestamp) -> ResT<Vec<MessageId>> {
+    let mut poll_message_ids = Vec::new();
+    let result = post_poll_messages(ctx, challenge, week_num, numsubs, poll_attachment, voting_end_time, &mut poll_message_ids).await;
+    if result.is_err() {
+        for id in &poll_message_ids {
+            if let Err(e) = challenge.announcement_channel().delete_message(ctx, *id).await {
+                err!("Error deleting partially posted poll message {}: {}", id, e);
+            }
+        }
+    }
+    result.map(|_| poll_message_ids)
+}
+
+/// Post the messages making up a poll, adding the id of each one to `poll_message_ids` as it is posted.
+async fn post_poll_messages(ctx: &Context, challenge: Challenge, week_num: i64, numsubs: usize, poll_attachment: CreateAttachment, voting_end_time: Timestamp, poll_message_ids: &mut Vec<MessageId>) -> Res {
+    // a message can only hold so many buttons, so we split them across as many messages as
+    // necessary; only the first of these carries the poll text and image
+    let prefix = format!("{}{:04}", challenge.one_char_name(), week_num);
+    let indices: Vec<usize> = (0..numsubs).collect();
+    let mut chunks = indices.chunks(POLL_BUTTONS_PER_MESSAGE);
+
+    let mut poll_message_builder = CreateMessage::new()
+        .content(format_poll_spiel(&voting_end_time.to_discord('F'), &voting_end_time.to_discord('R')))
+        .add_file(poll_attachment);
+    for idx in chunks.next().unwrap_or(&[]) {
+        poll_message_builder = poll_message_builder.button(poll_button(&prefix, *idx));
+    }
+    poll_message_ids.push(challenge.announcement_channel().send_message(&ctx, poll_message_builder).await?.id);
+
+    for chunk in chunks {
+        let mut extra_poll_message_builder = CreateMessage::new().content(EMPTY_MESSAGE);
+        for idx in chunk {
+            extra_poll_message_builder = extra_poll_message_builder.button(poll_button(&prefix, *idx));
+        }
+        poll_message_ids.push(challenge.announcement_channel().send_message(&ctx, extra_poll_message_builder).await?.id);
+    }
+    Ok(())
+}
+
+/// Start the next week of a challenge right away rather than at its scheduled time: end the current
+/// week if it is still running, initialise the next one from the queue if necessary, and roll over to it.
+/// The next week's target times are moved so that it starts now and lasts as long as it would have.
+#[instrument(name = "announce_now", skip(ctx, challenge), fields(challenge = challenge.short_name()))]
+pub async fn announce_now(ctx: &Context, challenge: Challenge) -> ResT<String> {
+    let _guard = ADVANCE_LOCK.get_or_init(Default::default).lock().await;
+    let current_week_num = get_current_week_num(challenge).await?;
+    let now = Utc::now();
+
+    let next_week_data = match get_week_info(current_week_num + 1, challenge).await {
+        Ok(mut next_week_data) => {
+            let duration = next_week_data.target_end_time.0.zip(next_week_data.target_start_time.0)
+                .map(|(end, start)| end - start)
+                .ok_or("Unexpected state: next week has no target times")?;
+            next_week_data.target_start_time = now.into();
+            next_week_data.target_end_time = (now + duration).into();
+            insert_or_modify_week(next_week_data.clone()).await?;
+            next_week_data
+        }
+        Err(_) => {
+            let Ok((_, prompt_id, next_prompt)) = get_next_prompt(challenge).await else {
+                return Ok(format!("No prompt in the queue to start week {} with.", current_week_num + 1));
+            };
+            let target_start_time: Timestamp = now.into();
+            let target_end_time = target_start_time + challenge.default_duration()
+                * next_prompt.custom_duration.unwrap_or(1) as i32 - config().time_gap;
+            info!("Initialising next week for challenge {}", challenge.short_name());
+            initialise_week(challenge, current_week_num + 1, prompt_id, &next_prompt, target_start_time, target_end_time).await?;
+            get_week_info(current_week_num + 1, challenge).await?
         }
+    };
+
+    if get_week_info(current_week_num, challenge).await?.actual_end_time == NULL_TIMESTAMP {
+        info!("Ending the current week for challenge {} early", challenge.short_name());
+        let concluded = end_week(challenge, current_week_num, now.into()).await?;
+        remove_absent_user_submissions(ctx, challenge, current_week_num, config().server_id).await?;
+        if concluded { send_vote_summaries(ctx, challenge, current_week_num - 1).await?; }
+    }
+
+    // re-read the current week, since ending it changed it
+    let current_week_info = get_week_info(current_week_num, challenge).await?;
+    roll_over(ctx, challenge, current_week_num, current_week_info, next_week_data).await
+}
+
+/// Move the end of the current week of a challenge by `extension`, reopening the week for submissions
+/// if it has already ended. If the next week has already been initialised, it is moved by the same
+/// amount. Returns the new end time.
+#[instrument(name = "extend_week", skip(challenge), fields(challenge = challenge.short_name()))]
+pub async fn extend_week(challenge: Challenge, extension: Duration) -> ResT<Timestamp> {
+    let _guard = ADVANCE_LOCK.get_or_init(Default::default).lock().await;
+    let current_week_num = get_current_week_num(challenge).await?;
+    let mut current_week_info = get_week_info(current_week_num, challenge).await?;
+    let target_end_time = current_week_info.target_end_time + extension;
+    if target_end_time.0.map_or(true, |t| t <= Utc::now()) {
+        return Err(format!("That would move the end of week {} into the past.", current_week_num).into());
+    }
+
+    if current_week_info.actual_end_time != NULL_TIMESTAMP {
+        // the poll is already up, so the submissions can't change anymore
+        if current_week_info.target_voting_end_time != NULL_TIMESTAMP {
+            return Err(format!("Voting on week {} has already opened, so it can't be reopened.", current_week_num).into());
+        }
+        info!("Reopening week {} of challenge {}", current_week_num, challenge.short_name());
+        current_week_info.actual_end_time = NULL_TIMESTAMP;
+    }
+    current_week_info.target_end_time = target_end_time;
+    insert_or_modify_week(current_week_info).await?;
+
+    if let Ok(mut next_week_info) = get_week_info(current_week_num + 1, challenge).await {
+        next_week_info.target_start_time += extension;
+        next_week_info.target_end_time += extension;
+        insert_or_modify_week(next_week_info).await?;
+    }
+    Ok(target_end_time)
+}
+
+/// Create the voting button for the `idx`th submission in a poll.
+fn poll_button(prefix: &str, idx: usize) -> CreateButton {
+    let button = CreateButton::new(format!("{}-{:03}", prefix, idx)).style(ButtonStyle::Primary);
+    // if we run out of emoji, number the remaining buttons instead
+    match VOTING_EMOJI_SEQUENCE.get(idx) {
+        Some(emoji) => button.emoji(*emoji),
+        None => button.label((idx + 1).to_string()),
+    }
+}
+
+/// DM everyone who voted in a week and asked for it a summary of their votes and the top 3.
+/// Users who can't be DMed are skipped.
+pub async fn send_vote_summaries(ctx: &Context, challenge: Challenge, week_num: i64) -> Res {
+    let users = get_vote_summary_users(challenge, week_num).await?;
+    if users.is_empty() { return Ok(()); }
+    let num_subs = get_week_info(week_num, challenge).await?.num_subs;
+    let top = rank_winners(tally_week(challenge, week_num).await?).into_iter().take(3).enumerate()
+        .map(|(place, (_, author, total))| format!("{}. <@{}> with {} vote{}", place + 1, author, total, if total == 1 { "" } else { "s" }))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    info!("Sending vote summaries for week {} to {} users", week_num, users.len());
+    for user_id in users {
+        let votes: Vec<String> = get_votes(challenge, week_num, user_id, num_subs).await?.iter().map(|x| VOTING_EMOJI_SEQUENCE.get(*x as usize)
+            .map(|x| x.display_string()).unwrap_or_else(|| format!("#{}", x + 1))).collect();
+        let content = format!("Voting for week {} of the {} challenge has ended.\nYour votes: {}\nTop submissions:\n{}",
+            week_num, challenge.name(), votes.join(" "), top);
+        let sent = async {
+            user_id.create_dm_channel(ctx).await?.send_message(ctx, CreateMessage::new().content(content)).await?;
+            Ok::<_, Error>(())
+        }.await;
+        // this mostly fails because the user has DMs closed, which is fine
+        if let Err(e) = sent { info!("Couldn't send vote summary to user {}: {}", user_id, e); }
     }
     Ok(())
 }
@@ -138,4 +481,25 @@ pub async fn remove_absent_user_submissions(ctx: &Context, challenge: Challenge,
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn week_ending_at(actual_end_time: Timestamp) -> WeekInfo {
+        let start = Timestamp::from(Utc::now() - Duration::weeks(1));
+        WeekInfo { challenge: Challenge::Glyph, week_num: 1, prompt_string: "a".to_owned(), size_percentage: 100,
+            target_start_time: start, target_end_time: actual_end_time, actual_start_time: start, actual_end_time,
+            is_special: false, num_subs: 0, poll_message_ids: Default::default(),
+            target_voting_end_time: NULL_TIMESTAMP, actual_voting_end_time: NULL_TIMESTAMP }
+    }
+
+    #[test]
+    fn future_end_time_is_an_error() {
+        let now = Utc::now();
+        assert!(has_ended(&week_ending_at(Timestamp::from(now + Duration::hours(1))), now).is_err());
+        assert!(has_ended(&week_ending_at(Timestamp::from(now - Duration::hours(1))), now).unwrap());
+        assert!(!has_ended(&week_ending_at(NULL_TIMESTAMP), now).unwrap());
+    }
+}
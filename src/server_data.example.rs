@@ -0,0 +1,50 @@
+//! Template for `server_data.rs`, which holds the ids and secrets of the server the bot runs
+//! in and is not checked in. Copy this file to `src/server_data.rs` and fill in the values.
+//! Everything else is configured in `glyfi.toml` instead; see the README.
+
+use chrono::Duration;
+use poise::serenity_prelude::{ChannelId, EmojiId, GuildId, UserId};
+use crate::types::AnyEmoji;
+
+/// Only used if the `DISCORD_BOT_TOKEN` environment variable is not set.
+pub const DISCORD_BOT_TOKEN: &str = "";
+
+pub const SERVER_ID: GuildId = GuildId::new(1);
+pub const GLYFI_USER_ID: UserId = UserId::new(1);
+pub const GLYPH_ANNOUNCEMENTS_CHANNEL_ID: ChannelId = ChannelId::new(1);
+pub const AMBIGRAM_ANNOUNCEMENTS_CHANNEL_ID: ChannelId = ChannelId::new(1);
+pub const GLYPH_SUBMISSION_CHANNEL_ID: ChannelId = ChannelId::new(1);
+pub const AMBIGRAM_SUBMISSION_CHANNEL_ID: ChannelId = ChannelId::new(1);
+pub const STATUS_UPDATE_CHANNEL_ID: ChannelId = ChannelId::new(1);
+pub const SUBMIT_EMOJI_ID: EmojiId = EmojiId::new(1);
+
+/// How long a week of each challenge lasts, and the gap between the end of one week and the start of the next.
+pub const GLYPH_INTERVAL: Duration = Duration::weeks(1);
+pub const AMBI_INTERVAL: Duration = Duration::weeks(2);
+pub const TIME_GAP: Duration = Duration::hours(1);
+
+/// Content of the extra poll messages that only hold buttons; Discord doesn't allow empty messages.
+pub const EMPTY_MESSAGE: &str = "\u{200B}";
+
+/// The emoji on the poll buttons, in order. Submissions past the end of this get a numbered button instead.
+pub const VOTING_EMOJI_SEQUENCE: [AnyEmoji; 26] = [
+    AnyEmoji::Default('🇦'), AnyEmoji::Default('🇧'), AnyEmoji::Default('🇨'), AnyEmoji::Default('🇩'),
+    AnyEmoji::Default('🇪'), AnyEmoji::Default('🇫'), AnyEmoji::Default('🇬'), AnyEmoji::Default('🇭'),
+    AnyEmoji::Default('🇮'), AnyEmoji::Default('🇯'), AnyEmoji::Default('🇰'), AnyEmoji::Default('🇱'),
+    AnyEmoji::Default('🇲'), AnyEmoji::Default('🇳'), AnyEmoji::Default('🇴'), AnyEmoji::Default('🇵'),
+    AnyEmoji::Default('🇶'), AnyEmoji::Default('🇷'), AnyEmoji::Default('🇸'), AnyEmoji::Default('🇹'),
+    AnyEmoji::Default('🇺'), AnyEmoji::Default('🇻'), AnyEmoji::Default('🇼'), AnyEmoji::Default('🇽'),
+    AnyEmoji::Default('🇾'), AnyEmoji::Default('🇿'),
+];
+
+pub fn format_glyph_announcement_spiel(week_num: i64, prompt_string: &str, full_timestamp: &str, relative_timestamp: &str) -> String {
+    format!("Glyph Challenge week {week_num}: **{prompt_string}**. Submissions close {full_timestamp} ({relative_timestamp}).")
+}
+
+pub fn format_ambi_announcement_spiel(week_num: i64, prompt_string: &str, full_timestamp: &str, relative_timestamp: &str) -> String {
+    format!("Ambigram Challenge week {week_num}: **{prompt_string}**. Submissions close {full_timestamp} ({relative_timestamp}).")
+}
+
+pub fn format_poll_spiel(full_timestamp: &str, relative_timestamp: &str) -> String {
+    format!("Vote for your favourite submissions by {full_timestamp} ({relative_timestamp})!")
+}
@@ -1,48 +1,81 @@
-use crate::server_data::{AMBI_INTERVAL, GLYPH_INTERVAL};
-use crate::types::{Challenge, PromptData, Timestamp, UserProfileData, WeekInfo};
-use crate::{info, info_sync, Error, Res, ResT};
+use crate::config::config;
+use crate::types::{Challenge, ChallengeStats, LeaderboardMetric, PromptData, Timestamp, UserProfileData, VoteLimitError, WeekInfo, NULL_TIMESTAMP};
+use crate::{err, info, Error, Res, ResT};
 use chrono::{DateTime, Duration, Utc};
 use const_format::formatcp;
+use rand::{rngs::StdRng, seq::SliceRandom};
 use poise::serenity_prelude::{Member, MessageId, UserId};
+use poise::serenity_prelude::futures::TryStreamExt;
 use poise::ChoiceParameter;
 use sqlx::migrate::MigrateDatabase;
-use sqlx::{FromRow, Sqlite, SqlitePool};
+use sqlx::{Executor, FromRow, Sqlite, SqlitePool, Transaction};
 use std::str::FromStr;
+use std::sync::OnceLock;
 use std::thread::current;
+use tokio::io::{AsyncWriteExt, BufWriter};
 
 pub const DB_PATH: &str = "glyfi.db";
 
-static mut __GLYFI_DB_POOL: Option<SqlitePool> = None;
+static __GLYFI_DB_POOL: OnceLock<SqlitePool> = OnceLock::new();
 
 /// Get the global sqlite connexion pool.
 fn pool() -> &'static SqlitePool {
-    unsafe { __GLYFI_DB_POOL.as_ref().unwrap() }
+    __GLYFI_DB_POOL.get().expect("Database accessed before it was initialised")
 }
 
-/*/// Merge the DB into one file.
-pub async fn truncate_wal() {
-    sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)").execute(pool()).await.unwrap();
+/// Check that the database can be queried.
+pub async fn ping_db() -> Res {
+    sqlx::query("SELECT 1").execute(pool()).await?;
+    Ok(())
+}
+
+/// Merge the DB into one file.
+pub async fn truncate_wal() -> Res {
+    sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)").execute(pool()).await?;
+    Ok(())
+}
+
+/// Write a consistent copy of the database to a timestamped file, returning its path.
+/// A backup made earlier on the same day is overwritten.
+pub async fn backup_db() -> ResT<String> {
+    let path = format!("glyfi-backup-{}.db", Utc::now().format("%Y%m%d"));
+    if let Err(e) = tokio::fs::remove_file(&path).await {
+        if e.kind() != std::io::ErrorKind::NotFound { return Err(e.into()); }
+    }
+    truncate_wal().await?;
+    sqlx::query("VACUUM INTO ?").bind(&path).execute(pool()).await?;
+    Ok(path)
 }
-*/
 
 /// Only intended to be called by [`terminate()`].
-pub async unsafe fn __glyfi_fini_db() {
-    if let Some(pool) = __GLYFI_DB_POOL.as_ref() {
+pub async fn __glyfi_fini_db() {
+    if let Some(pool) = __GLYFI_DB_POOL.get() {
+        // Merge the WAL into the main file so that it is complete on its own.
+        info!("Checkpointing WAL...");
+        if let Err(e) = truncate_wal().await { err!("Failed to checkpoint WAL: {}", e); }
+        info!("Closing DB connections...");
         pool.close().await;
     }
 }
 
 /// Only intended to be called by main().
-pub async unsafe fn __glyfi_init_db() {
+pub async fn __glyfi_init_db() {
     // Create the database if it doesn’t exist yet.
-    info_sync!("Initialising sqlite db...");
+    info!("Initialising sqlite db...");
     if let Err(e) = Sqlite::create_database(DB_PATH).await {
         panic!("Failed to create sqlite db: {}", e);
     }
 
     // Create DB connexion.
-    __GLYFI_DB_POOL = Some(SqlitePool::connect(DB_PATH).await.unwrap());
+    if __GLYFI_DB_POOL.set(SqlitePool::connect(DB_PATH).await.unwrap()).is_err() {
+        panic!("Sqlite db initialised twice");
+    }
+
+    create_tables(pool()).await;
+}
 
+/// Create any tables that don’t exist yet and migrate older databases.
+async fn create_tables(pool: &SqlitePool) {
     // Create submissions table.
     sqlx::query(
         r#"
@@ -58,10 +91,21 @@ pub async unsafe fn __glyfi_init_db() {
         ) STRICT;
     "#,
     )
-    .execute(pool())
+    .execute(pool)
     .await
     .unwrap();
 
+    // Submissions are mostly looked up by week or by author. `IF NOT EXISTS`
+    // means that existing databases pick these up too.
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_submissions_challenge_week ON submissions(challenge, week_num)")
+        .execute(pool)
+        .await
+        .unwrap();
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_submissions_author ON submissions(author)")
+        .execute(pool)
+        .await
+        .unwrap();
+
     // Cached user profile data (excludes current week, obviously).
     sqlx::query(
         r#"
@@ -81,14 +125,25 @@ pub async unsafe fn __glyfi_init_db() {
 
             -- Highest ranking in either challenge.
             highest_ranking_glyphs INTEGER NOT NULL DEFAULT 0,
-            highest_ranking_ambigrams INTEGER NOT NULL DEFAULT 0
+            highest_ranking_ambigrams INTEGER NOT NULL DEFAULT 0,
+
+            -- Whether to DM the user a summary of their votes when voting ends.
+            vote_summaries INTEGER NOT NULL DEFAULT 0
         ) STRICT;
     "#,
     )
-    .execute(pool())
+    .execute(pool)
     .await
     .unwrap();
 
+    if !has_column(pool, "users", "vote_summaries").await {
+        info!("Adding vote summary setting to users...");
+        sqlx::query("ALTER TABLE users ADD COLUMN vote_summaries INTEGER NOT NULL DEFAULT 0")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
     // The current week. This is a table with a single entry.
     sqlx::query(
         r#"
@@ -98,15 +153,32 @@ pub async unsafe fn __glyfi_init_db() {
         ) STRICT;
     "#,
     )
-    .execute(pool())
+    .execute(pool)
     .await
     .unwrap();
 
-    let _ = sqlx::query("INSERT OR IGNORE INTO current_week_num (challenge, week_num) VALUES (0, 0)")
-        .execute(pool())
-        .await;
-    let _ = sqlx::query("INSERT OR IGNORE INTO current_week_num (challenge, week_num) VALUES (1, 0)")
-        .execute(pool())
+    for challenge in Challenge::all() {
+        let _ = sqlx::query("INSERT OR IGNORE INTO current_week_num (challenge, week_num) VALUES (?, 0)")
+            .bind(challenge.raw() as i64)
+            .execute(pool)
+            .await;
+    }
+
+    // Whether maintenance mode is on. This is a table with a single entry.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS maintenance (
+            id INTEGER NOT NULL PRIMARY KEY CHECK (id = 0),
+            enabled INTEGER NOT NULL
+        ) STRICT;
+    "#,
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+
+    let _ = sqlx::query("INSERT OR IGNORE INTO maintenance (id, enabled) VALUES (0, 0)")
+        .execute(pool)
         .await;
 
     // Table that stores what weeks are/were regular or special.
@@ -123,16 +195,90 @@ pub async unsafe fn __glyfi_init_db() {
             actual_end_time INTEGER,
             is_special INTEGER,
             num_subs INTEGER,
-            poll_message_id INTEGER,
-            second_poll_message_id INTEGER,
+            poll_message_ids TEXT NOT NULL DEFAULT '[]', -- JSON array of message IDs.
+            winners_recorded INTEGER NOT NULL DEFAULT 0, -- Whether placements have been added to user profiles.
+            reminder_sent INTEGER NOT NULL DEFAULT 0, -- Whether the deadline reminder has been posted.
+            announcement_message_id INTEGER, -- NULL if the announcement hasn't been posted.
+            prompt_id INTEGER, -- Rowid in prompts of the queue entry the week was initialised from.
+            target_voting_end_time INTEGER, -- NULL if voting lasts until the end of the next week.
+            actual_voting_end_time INTEGER,
             PRIMARY KEY (week_num, challenge)
         ) STRICT;
     "#,
     )
-    .execute(pool())
+    .execute(pool)
     .await
     .unwrap();
 
+    // Older databases store at most two poll messages per week in separate columns.
+    if !has_column(pool, "weeks", "poll_message_ids").await {
+        info!("Migrating poll message ids...");
+        let mut tx = pool.begin().await.unwrap();
+        for query in [
+            "ALTER TABLE weeks ADD COLUMN poll_message_ids TEXT NOT NULL DEFAULT '[]'",
+            r#"UPDATE weeks SET poll_message_ids = CASE
+                WHEN poll_message_id IS NULL THEN '[]'
+                WHEN second_poll_message_id IS NULL THEN json_array(poll_message_id)
+                ELSE json_array(poll_message_id, second_poll_message_id)
+            END"#,
+            "ALTER TABLE weeks DROP COLUMN poll_message_id",
+            "ALTER TABLE weeks DROP COLUMN second_poll_message_id",
+        ] {
+            sqlx::query(query).execute(&mut *tx).await.unwrap();
+        }
+        tx.commit().await.unwrap();
+    }
+
+    if !has_column(pool, "weeks", "winners_recorded").await {
+        info!("Adding winners_recorded column to weeks...");
+        sqlx::query("ALTER TABLE weeks ADD COLUMN winners_recorded INTEGER NOT NULL DEFAULT 0")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    if !has_column(pool, "weeks", "reminder_sent").await {
+        info!("Adding reminder_sent column to weeks...");
+        sqlx::query("ALTER TABLE weeks ADD COLUMN reminder_sent INTEGER NOT NULL DEFAULT 0")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    if !has_column(pool, "weeks", "announcement_message_id").await {
+        info!("Adding announcement_message_id column to weeks...");
+        sqlx::query("ALTER TABLE weeks ADD COLUMN announcement_message_id INTEGER")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    if !has_column(pool, "weeks", "prompt_id").await {
+        info!("Adding prompt_id column to weeks...");
+        sqlx::query("ALTER TABLE weeks ADD COLUMN prompt_id INTEGER")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    // Older databases don’t record when voting ended; voting on a week used to always
+    // end together with the week after it.
+    if !has_column(pool, "weeks", "actual_voting_end_time").await {
+        info!("Migrating voting end times...");
+        let mut tx = pool.begin().await.unwrap();
+        for query in [
+            "ALTER TABLE weeks ADD COLUMN target_voting_end_time INTEGER",
+            "ALTER TABLE weeks ADD COLUMN actual_voting_end_time INTEGER",
+            r#"UPDATE weeks SET actual_voting_end_time = (
+                SELECT next.actual_end_time FROM weeks AS next
+                WHERE next.challenge = weeks.challenge AND next.week_num = weeks.week_num + 1
+            )"#,
+        ] {
+            sqlx::query(query).execute(&mut *tx).await.unwrap();
+        }
+        tx.commit().await.unwrap();
+    }
+
     // Table that stores future prompts.
     sqlx::query(
         r#"
@@ -142,40 +288,117 @@ pub async unsafe fn __glyfi_init_db() {
             size_percentage INTEGER,
             custom_duration INTEGER,
             is_special INTEGER,
-            extra_announcement_text TEXT
+            extra_announcement_text TEXT,
+            position INTEGER NOT NULL DEFAULT 0, -- 1-based position of the prompt in its queue.
+            skipped INTEGER NOT NULL DEFAULT 0, -- Whether the scheduler passes over this prompt.
+            fixed_start_time INTEGER -- Unix timestamp before which the prompt must not start.
         ) STRICT;
         "#,
     )
-    .execute(pool())
+    .execute(pool)
     .await
     .unwrap();
 
-    // Table that stores votes. `votes` is an i64 with bitfields for each submission.
+    // Older databases order queues by rowid; number the prompts in that order.
+    if !has_column(pool, "prompts", "position").await {
+        info!("Migrating prompt positions...");
+        let mut tx = pool.begin().await.unwrap();
+        for query in [
+            "ALTER TABLE prompts ADD COLUMN position INTEGER NOT NULL DEFAULT 0",
+            r#"UPDATE prompts SET position = (
+                SELECT COUNT(*) FROM prompts AS p WHERE p.challenge = prompts.challenge AND p.rowid <= prompts.rowid
+            )"#,
+        ] {
+            sqlx::query(query).execute(&mut *tx).await.unwrap();
+        }
+        tx.commit().await.unwrap();
+    }
+
+    if !has_column(pool, "prompts", "skipped").await {
+        info!("Adding skipped column to prompts...");
+        sqlx::query("ALTER TABLE prompts ADD COLUMN skipped INTEGER NOT NULL DEFAULT 0")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    if !has_column(pool, "prompts", "fixed_start_time").await {
+        info!("Adding fixed_start_time column to prompts...");
+        sqlx::query("ALTER TABLE prompts ADD COLUMN fixed_start_time INTEGER")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    // Table that stores votes, one row per submission a user voted for. `submission_index`
+    // is the position of the submission in the order returned by `get_submissions`.
     sqlx::query(
         r#"
-        CREATE TABLE IF NOT EXISTS votes (
+        CREATE TABLE IF NOT EXISTS vote_entries (
             challenge INTEGER NOT NULL,
-            week_num INTEGER,
-            user INTEGER,
-            votes INTEGER,
-            PRIMARY KEY(challenge, week_num, user)
+            week_num INTEGER NOT NULL,
+            user INTEGER NOT NULL,
+            submission_index INTEGER NOT NULL,
+            PRIMARY KEY(challenge, week_num, user, submission_index)
         ) STRICT;
         "#,
     )
-    .execute(pool())
+    .execute(pool)
     .await
     .unwrap();
+
+    // Older databases store each user's votes as an i64 bitfield, which can't
+    // represent more than 64 submissions; split those up into separate rows.
+    if has_table(pool, "votes").await {
+        info!("Migrating votes...");
+        let mut tx = pool.begin().await.unwrap();
+        sqlx::query(
+            r#"
+            WITH RECURSIVE bits(n) AS (SELECT 0 UNION ALL SELECT n + 1 FROM bits WHERE n < 63)
+            INSERT OR IGNORE INTO vote_entries (challenge, week_num, user, submission_index)
+            SELECT challenge, week_num, user, n FROM votes, bits WHERE (votes >> n) & 1 = 1;
+            "#,
+        )
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+        sqlx::query("DROP TABLE votes").execute(&mut *tx).await.unwrap();
+        tx.commit().await.unwrap();
+    }
 }
 
 /////////////////////////////////////////////////////////////////////
 /////////////////////////////////////////////////////////////////////
 
-/// Add a user to the database.
+/// Check whether a table exists; used to migrate older databases.
+async fn has_table(pool: &SqlitePool, table: &str) -> bool {
+    sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?")
+        .bind(table)
+        .fetch_one(pool)
+        .await
+        .map(|count: i64| count > 0)
+        .unwrap()
+}
+
+/// Check whether a table has a column; used to migrate older databases.
+async fn has_column(pool: &SqlitePool, table: &str, column: &str) -> bool {
+    sqlx::query_scalar("SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = ?")
+        .bind(table)
+        .bind(column)
+        .fetch_one(pool)
+        .await
+        .map(|count: i64| count > 0)
+        .unwrap()
+}
+
+/// Add a user to the database, if they aren't in it already. If they are, but don't
+/// have a nickname yet, theirs is set from the member.
 pub async fn register_user(member: Member) -> Res {
     sqlx::query(
         r#"
-    INSERT INTO users (id, nickname) VALUES (?, ?);
-        "#,
+        INSERT INTO users (id, nickname) VALUES (?1, ?2)
+        ON CONFLICT (id) DO UPDATE SET nickname = COALESCE(nickname, ?2);
+    "#,
     )
     .bind(member.user.id.get() as i64)
     .bind(member.nick.unwrap_or(member.user.name))
@@ -205,14 +428,27 @@ pub async fn check_submission(message_id: MessageId) -> ResT<bool> {
         .map_err(|e| e.into())
 }
 
-/// Add a submission to the database.
+/// Add a submission to the database. Returns whether it was added; adding a submission that
+/// is already there does nothing, since Discord may deliver the same reaction more than once.
 pub async fn register_submission(
     message: MessageId,
     challenge: Challenge,
     author: UserId,
     link: &str,
     week_num: i64,
-) -> Res {
+) -> ResT<bool> {
+    register_submission_with(pool(), message, challenge, author, link, week_num).await
+}
+
+/// [`register_submission`], but using a specific executor, e.g. a transaction.
+async fn register_submission_with<'e, E: Executor<'e, Database = Sqlite>>(
+    executor: E,
+    message: MessageId,
+    challenge: Challenge,
+    author: UserId,
+    link: &str,
+    week_num: i64,
+) -> ResT<bool> {
     sqlx::query(
         r#"
     INSERT INTO submissions (
@@ -221,7 +457,8 @@ pub async fn register_submission(
         challenge,
             author,
             link
-        ) VALUES (?, ?, ?, ?, ?);
+        ) VALUES (?, ?, ?, ?, ?)
+        ON CONFLICT (message, week_num, challenge) DO NOTHING;
         "#,
     )
     .bind(message.get() as i64)
@@ -229,14 +466,31 @@ pub async fn register_submission(
     .bind(challenge as i64)
     .bind(author.get() as i64)
     .bind(link)
-    .execute(pool())
+    .execute(executor)
     .await
-    .map(|_| ())
+    .map(|r| r.rows_affected() > 0)
     .map_err(|e| e.into())
 }
 /// Remove a submission from the database.
+///
+/// Votes refer to submissions by their index in [`get_submissions`], so the votes for the removed
+/// submission are deleted, and the votes for every later submission are shifted down by one so they
+/// keep referring to the same submission.
 pub async fn deregister_submission(message: MessageId, challenge: Challenge, week_num: i64) -> Res {
-    sqlx::query(
+    deregister_submission_with(pool(), message, challenge, week_num).await
+}
+
+/// [`deregister_submission`], but using a specific connexion pool.
+async fn deregister_submission_with(pool: &SqlitePool, message: MessageId, challenge: Challenge, week_num: i64) -> Res {
+    let mut tx = pool.begin().await?;
+    let index: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM submissions WHERE message < ? AND week_num = ? AND challenge = ?")
+        .bind(message.get() as i64)
+        .bind(week_num)
+        .bind(challenge as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    let removed = sqlx::query(
         r#"
             DELETE FROM submissions
             WHERE message = ?
@@ -247,10 +501,34 @@ pub async fn deregister_submission(message: MessageId, challenge: Challenge, wee
     .bind(message.get() as i64)
     .bind(week_num)
     .bind(challenge as i64)
-    .execute(pool())
-    .await
-    .map(|_| ())
-    .map_err(|e| e.into())
+    .execute(&mut *tx)
+    .await?
+    .rows_affected() > 0;
+    if !removed { return Ok(()); }
+
+    sqlx::query("DELETE FROM vote_entries WHERE challenge = ? AND week_num = ? AND submission_index = ?")
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .bind(index)
+        .execute(&mut *tx)
+        .await?;
+
+    // Shift in two steps via negative indices, since shifting in place could transiently
+    // collide with another of the same user's votes and violate the primary key.
+    sqlx::query("UPDATE vote_entries SET submission_index = -(submission_index - 1) WHERE challenge = ? AND week_num = ? AND submission_index > ?")
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .bind(index)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("UPDATE vote_entries SET submission_index = -submission_index WHERE challenge = ? AND week_num = ? AND submission_index < 0")
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
 }
 
 /// Get all the submissions from a particular week and challenge, along with the users who posted them.
@@ -264,26 +542,81 @@ pub async fn get_submissions(challenge: Challenge, week_num: i64) -> ResT<Vec<(U
         .map(|x| x.into_iter().map(|(a,b): (i64, i64)| (UserId::new(a as u64), MessageId::new(b as u64))).collect())
 }
 
+/// Get all submissions a user has made, optionally only to one challenge, most recent week first.
+/// Returns the week number, message, and stored link of each submission.
+pub async fn get_user_submissions(user: UserId, challenge: Option<Challenge>) -> ResT<Vec<(i64, MessageId, String)>> {
+    sqlx::query_as(r#"
+        SELECT week_num, message, link FROM submissions
+        WHERE author = ?1 AND (?2 IS NULL OR challenge = ?2)
+        ORDER BY week_num DESC, challenge ASC, message ASC;
+    "#)
+        .bind(user.get() as i64)
+        .bind(challenge.map(|c| c.raw() as i64))
+        .fetch_all(pool())
+        .await
+        .map_err(|e| e.into())
+        .map(|x| x.into_iter().map(|(w, m, l): (i64, i64, String)| (w, MessageId::new(m as u64), l)).collect())
+}
+
+/// Count how many submissions a user has made to a challenge in a week.
+pub async fn count_user_submissions(challenge: Challenge, week_num: i64, user: UserId) -> ResT<i64> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM submissions WHERE challenge = ? AND week_num = ? AND author = ?")
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .bind(user.get() as i64)
+        .fetch_one(pool())
+        .await
+        .map_err(|e| e.into())
+}
+
 /// Get the current week num.
 pub async fn get_current_week_num(challenge: Challenge) -> ResT<i64> {
+    get_current_week_num_with(pool(), challenge).await
+}
+
+/// [`get_current_week_num`], but using a specific executor, e.g. a transaction.
+async fn get_current_week_num_with<'e, E: Executor<'e, Database = Sqlite>>(executor: E, challenge: Challenge) -> ResT<i64> {
     sqlx::query_scalar("SELECT week_num FROM current_week_num WHERE challenge = ? LIMIT 1;")
         .bind(challenge.raw() as i64)
-        .fetch_one(pool())
+        .fetch_one(executor)
         .await
         .map_err(|e| format!("Failed to get current week: {}", e).into())
 }
 
 /// Set the current week num. Returns whether the operation was successful.
 pub async fn set_current_week_num(challenge: Challenge, week_num: i64) -> ResT<bool> {
+    set_current_week_num_with(pool(), challenge, week_num).await
+}
+
+/// [`set_current_week_num`], but using a specific executor, e.g. a transaction.
+async fn set_current_week_num_with<'e, E: Executor<'e, Database = Sqlite>>(executor: E, challenge: Challenge, week_num: i64) -> ResT<bool> {
     sqlx::query("UPDATE current_week_num SET week_num = ? WHERE challenge = ?")
         .bind(week_num)
         .bind(challenge.raw() as i64)
-        .execute(pool())
+        .execute(executor)
         .await
         .map(|r| r.rows_affected() > 0)
         .map_err(|e| e.into())
 }
 
+/// Get whether maintenance mode is on.
+pub async fn get_maintenance_mode() -> ResT<bool> {
+    sqlx::query_scalar("SELECT enabled FROM maintenance WHERE id = 0;")
+        .fetch_one(pool())
+        .await
+        .map_err(|e| e.into())
+}
+
+/// Turn maintenance mode on or off.
+pub async fn set_maintenance_mode(enabled: bool) -> Res {
+    sqlx::query("UPDATE maintenance SET enabled = ? WHERE id = 0")
+        .bind(enabled)
+        .execute(pool())
+        .await
+        .map(|_| ())
+        .map_err(|e| e.into())
+}
+
 /// Get profile data for a user.
 pub async fn get_user_profile(user: UserId) -> ResT<UserProfileData> {
     #[derive(Default, FromRow)]
@@ -359,6 +692,138 @@ pub async fn get_user_profile(user: UserId) -> ResT<UserProfileData> {
     })
 }
 
+/// Write every user’s profile, including their submission counts, to a timestamped CSV file,
+/// returning its path. Unless `include_ids` is set, users are only identified by their row
+/// number, and their nicknames are left out as well. Rows are written as they are read, so
+/// the table is never held in memory all at once.
+pub async fn export_users(include_ids: bool) -> ResT<String> {
+    #[derive(FromRow)]
+    struct UserRow {
+        id: i64,
+        nickname: Option<String>,
+        glyphs_first: i64,
+        glyphs_second: i64,
+        glyphs_third: i64,
+        ambigrams_first: i64,
+        ambigrams_second: i64,
+        ambigrams_third: i64,
+        highest_ranking_glyphs: i64,
+        highest_ranking_ambigrams: i64,
+        glyphs_submissions: i64,
+        ambigrams_submissions: i64,
+    }
+
+    // Quote a field if it contains anything that would otherwise break the row up.
+    fn csv_field(s: &str) -> String {
+        if s.contains([',', '"', '\n', '\r']) { format!("\"{}\"", s.replace('"', "\"\"")) } else { s.to_owned() }
+    }
+
+    let path = format!("glyfi-users-{}.csv", Utc::now().format("%Y%m%d"));
+    let mut out = BufWriter::new(tokio::fs::File::create(&path).await?);
+    let identity = if include_ids { "id,nickname" } else { "user" };
+    out.write_all(format!("{identity},glyphs_first,glyphs_second,glyphs_third,ambigrams_first,ambigrams_second,ambigrams_third,\
+        highest_ranking_glyphs,highest_ranking_ambigrams,glyphs_submissions,ambigrams_submissions\n").as_bytes()).await?;
+
+    let mut rows = sqlx::query_as::<_, UserRow>(r#"
+        SELECT
+            users.id, users.nickname,
+            glyphs_first, glyphs_second, glyphs_third,
+            ambigrams_first, ambigrams_second, ambigrams_third,
+            highest_ranking_glyphs, highest_ranking_ambigrams,
+            (SELECT COUNT(*) FROM submissions WHERE author = users.id AND challenge = ?1) AS glyphs_submissions,
+            (SELECT COUNT(*) FROM submissions WHERE author = users.id AND challenge = ?2) AS ambigrams_submissions
+        FROM users
+        ORDER BY users.id ASC;
+    "#)
+        .bind(Challenge::Glyph.raw() as i64)
+        .bind(Challenge::Ambigram.raw() as i64)
+        .fetch(pool());
+
+    let mut row_num = 0;
+    while let Some(row) = rows.try_next().await? {
+        row_num += 1;
+        let identity = if include_ids {
+            format!("{},{}", row.id, csv_field(row.nickname.as_deref().unwrap_or("")))
+        } else {
+            row_num.to_string()
+        };
+        out.write_all(format!("{},{},{},{},{},{},{},{},{},{},{}\n", identity,
+            row.glyphs_first, row.glyphs_second, row.glyphs_third,
+            row.ambigrams_first, row.ambigrams_second, row.ambigrams_third,
+            row.highest_ranking_glyphs, row.highest_ranking_ambigrams,
+            row.glyphs_submissions, row.ambigrams_submissions).as_bytes()).await?;
+    }
+    out.flush().await?;
+    Ok(path)
+}
+
+/// Get the top users by some metric, optionally only counting one challenge. Returns
+/// each user’s id, nickname (if set), and score.
+pub async fn get_leaderboard(metric: LeaderboardMetric, challenge: Option<Challenge>, limit: i64) -> ResT<Vec<(UserId, Option<String>, i64)>> {
+    let rows: Vec<(i64, Option<String>, i64)> = match metric {
+        LeaderboardMetric::FirstPlaces => {
+            // Ties are broken by number of second places, then by user id.
+            let (first, second) = match challenge {
+                Some(Challenge::Glyph) => ("glyphs_first", "glyphs_second"),
+                Some(Challenge::Ambigram) => ("ambigrams_first", "ambigrams_second"),
+                None => ("glyphs_first + ambigrams_first", "glyphs_second + ambigrams_second"),
+            };
+            let query = format!(r#"
+                SELECT id, nickname, {first} AS score
+                FROM users
+                WHERE {first} > 0
+                ORDER BY score DESC, {second} DESC, id ASC
+                LIMIT ?;
+            "#);
+            sqlx::query_as(&query)
+                .bind(limit)
+                .fetch_all(pool())
+                .await
+        }
+        LeaderboardMetric::Submissions => {
+            sqlx::query_as(r#"
+                SELECT submissions.author, users.nickname, COUNT(*) AS score
+                FROM submissions
+                LEFT JOIN users ON users.id = submissions.author
+                WHERE ?1 IS NULL OR submissions.challenge = ?1
+                GROUP BY submissions.author
+                ORDER BY score DESC, submissions.author ASC
+                LIMIT ?2;
+            "#)
+                .bind(challenge.map(|c| c.raw() as i64))
+                .bind(limit)
+                .fetch_all(pool())
+                .await
+        }
+    }
+    .map_err(|e| format!("Failed to get leaderboard: {}", e))?;
+
+    Ok(rows.into_iter().map(|(id, nickname, score)| (UserId::new(id as u64), nickname, score)).collect())
+}
+
+/// Get totals across all weeks of a challenge.
+pub async fn challenge_stats(challenge: Challenge) -> ResT<ChallengeStats> {
+    let weeks: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM weeks WHERE challenge = ? AND actual_start_time IS NOT NULL")
+        .bind(challenge.raw() as i16)
+        .fetch_one(pool())
+        .await?;
+    let (submissions, participants): (i64, i64) = sqlx::query_as("SELECT COUNT(*), COUNT(DISTINCT author) FROM submissions WHERE challenge = ?")
+        .bind(challenge.raw() as i16)
+        .fetch_one(pool())
+        .await?;
+    let busiest_week: Option<(i64, i64)> = sqlx::query_as(r#"
+        SELECT week_num, COUNT(*) AS total FROM submissions
+        WHERE challenge = ?
+        GROUP BY week_num
+        ORDER BY total DESC, week_num ASC
+        LIMIT 1;
+    "#)
+        .bind(challenge.raw() as i16)
+        .fetch_optional(pool())
+        .await?;
+    Ok(ChallengeStats { weeks, submissions, participants, busiest_week })
+}
+
 /// Set a user’s nickname.
 pub async fn set_nickname(user: UserId, name: &str) -> Res {
     sqlx::query(
@@ -375,50 +840,208 @@ pub async fn set_nickname(user: UserId, name: &str) -> Res {
     .map_err(|e| e.into())
 }
 
+/// Set whether a user wants to be sent a summary of their votes when voting ends.
+pub async fn set_vote_summaries(user: UserId, enabled: bool) -> Res {
+    sqlx::query(
+        r#"
+        INSERT INTO users (id, vote_summaries) VALUES (?1, ?2)
+        ON CONFLICT (id) DO UPDATE SET vote_summaries = ?2;
+    "#,
+    )
+    .bind(user.get() as i64)
+    .bind(enabled)
+    .execute(pool())
+    .await
+    .map(|_| ())
+    .map_err(|e| e.into())
+}
+
+/// Get the users who voted in a given week and want to be sent a summary of their votes.
+pub async fn get_vote_summary_users(challenge: Challenge, week_num: i64) -> ResT<Vec<UserId>> {
+    sqlx::query_scalar(
+        r#"
+        SELECT DISTINCT vote_entries.user FROM vote_entries
+        JOIN users ON users.id = vote_entries.user
+        WHERE vote_entries.challenge = ? AND vote_entries.week_num = ? AND users.vote_summaries = 1
+    "#)
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .fetch_all(pool())
+        .await
+        .map(|x: Vec<i64>| x.into_iter().map(|id| UserId::new(id as u64)).collect())
+        .map_err(|e| e.into())
+}
+
 /// Set the prompt for a challenge and week.
 /// Returns the id of the prompt in the DB.
 pub async fn add_prompt(prompt_data: &PromptData) -> ResT<i64> {
-    sqlx::query_scalar("INSERT INTO prompts (challenge, prompt_string, size_percentage, custom_duration, is_special, extra_announcement_text) VALUES (?, ?, ?, ?, ?, ?) RETURNING rowid")
+    add_prompt_with(pool(), prompt_data).await
+}
+
+/// [`add_prompt`], but using a specific executor, e.g. a transaction.
+async fn add_prompt_with<'e, E: Executor<'e, Database = Sqlite>>(executor: E, prompt_data: &PromptData) -> ResT<i64> {
+    sqlx::query_scalar(r#"
+    INSERT INTO prompts (challenge, prompt_string, size_percentage, custom_duration, is_special, extra_announcement_text, skipped, fixed_start_time, position)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, (SELECT COALESCE(MAX(position), 0) + 1 FROM prompts WHERE challenge = ?1))
+    RETURNING rowid
+    "#)
         .bind(prompt_data.challenge.raw())
         .bind(&prompt_data.prompt_string)
         .bind(prompt_data.size_percentage.map(|x| x as i32))
         .bind(prompt_data.custom_duration.map(|x| x as i32))
         .bind(prompt_data.is_special)
         .bind(&prompt_data.extra_announcement_text)
-        .fetch_one(pool())
+        .bind(prompt_data.skipped)
+        .bind(prompt_data.fixed_start_time)
+        .fetch_one(executor)
         .await
         .map_err(|e| e.into())
 }
 
+/// Insert a prompt so that it ends up at the given position in its queue, or at the
+/// end if the queue is shorter than that. Returns the position it ended up at.
+pub async fn insert_prompt_at(challenge: Challenge, position: usize, prompt_data: &PromptData) -> ResT<usize> {
+    if position < 1 { return Err("Invalid position value.".into()); }
+    let mut tx = pool().begin().await?;
+    add_prompt_with(&mut *tx, prompt_data).await?;
+    let mut ids = get_prompt_ids_with(&mut *tx, challenge).await?;
+
+    // The new prompt was added at the end, so move it into place from there.
+    let position = position.min(ids.len());
+    let id = ids.pop().ok_or("Inserted prompt is missing from the queue.")?;
+    ids.insert(position - 1, id);
+    if !reorder_queue(&mut tx, &ids).await? {
+        return Err("Database operation failed while inserting prompt.".into());
+    }
+    tx.commit().await?;
+    Ok(position)
+}
+
+/// Add several prompts to the end of a queue at once, optionally removing all
+/// prompts that were previously in that queue. Either all of them are added or none are.
+pub async fn import_prompts(challenge: Challenge, prompts: &[PromptData], replace: bool) -> Res {
+    let mut tx = pool().begin().await?;
+    if replace {
+        sqlx::query("DELETE FROM prompts WHERE challenge = ?")
+            .bind(challenge.raw())
+            .execute(&mut *tx)
+            .await?;
+    }
+    for prompt_data in prompts {
+        add_prompt_with(&mut *tx, prompt_data).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
 /// Swaps two prompts within a given queue. Returns whether the operation was successful
 pub async fn swap_prompts(challenge: Challenge, pos1: usize, pos2: usize) -> ResT<bool> {
-    let (id1, prompt_data1) = get_prompt_id_data(challenge, pos1).await?;
-    let (id2, prompt_data2) = get_prompt_id_data(challenge, pos2).await?;
-    Ok(edit_prompt(id1, &prompt_data2).await? & edit_prompt(id2, &prompt_data1).await?)
+    let mut tx = pool().begin().await?;
+    let mut ids = get_prompt_ids_with(&mut *tx, challenge).await?;
+    let len = ids.len();
+    if !(1..=len).contains(&pos1) || !(1..=len).contains(&pos2) {
+        return Err(format!("Positions must be between 1 and {len} for challenge {}.", challenge.name()).into());
+    }
+
+    // both rows are updated in the same transaction, so if either update fails, neither is applied
+    ids.swap(pos1 - 1, pos2 - 1);
+    if !reorder_queue(&mut tx, &ids).await? { return Ok(false); }
+    tx.commit().await?;
+    Ok(true)
 }
 
-/// Delete the nth prompt in a given queue. Returns whether the operation was successful.
+/// Delete the nth prompt in a given queue, moving the prompts after it up by one.
+/// Returns whether the operation was successful.
 pub async fn delete_prompt(challenge: Challenge, position: usize) -> ResT<bool> {
-    let id = get_prompt_id(challenge, position).await?;
-    sqlx::query("DELETE FROM prompts WHERE rowid = ?")
+    let mut tx = pool().begin().await?;
+    let ids = get_prompt_ids_with(&mut *tx, challenge).await?;
+    let Some(&id) = position.checked_sub(1).and_then(|idx| ids.get(idx)) else {
+        return Err("No prompt found at given position.".into());
+    };
+    if !remove_prompt_with(&mut tx, &ids, id).await? { return Ok(false); }
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Delete the prompt with a given id from a queue, moving the prompts after it up by one.
+/// Returns whether the operation was successful, which it isn't if there is no such prompt.
+pub async fn delete_prompt_by_id(challenge: Challenge, id: i64) -> ResT<bool> {
+    let mut tx = pool().begin().await?;
+    let ids = get_prompt_ids_with(&mut *tx, challenge).await?;
+    if !ids.contains(&id) { return Ok(false); }
+    if !remove_prompt_with(&mut tx, &ids, id).await? { return Ok(false); }
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Delete the prompt with a given id, given the ids of all prompts in its queue in queue order,
+/// and renumber the rest. Returns whether the operation was successful.
+async fn remove_prompt_with(tx: &mut Transaction<'_, Sqlite>, ids: &[i64], id: i64) -> ResT<bool> {
+    let removed = sqlx::query("DELETE FROM prompts WHERE rowid = ?")
         .bind(id)
+        .execute(&mut **tx)
+        .await?
+        .rows_affected() > 0;
+    if !removed { return Ok(false); }
+
+    let remaining: Vec<i64> = ids.iter().filter(|x| **x != id).copied().collect();
+    reorder_queue(tx, &remaining).await
+}
+
+/// Delete the prompts at positions `from` through `to` (inclusive) in a given queue, shifting the
+/// ones after them back. Returns the number of prompts deleted.
+pub async fn delete_prompts(challenge: Challenge, from: usize, to: usize) -> ResT<u64> {
+    let mut tx = pool().begin().await?;
+    let ids = get_prompt_ids_with(&mut *tx, challenge).await?;
+    let len = ids.len();
+    if from > to { return Err("The start of the range must not be after its end.".into()); }
+    if !(1..=len).contains(&from) || !(1..=len).contains(&to) {
+        return Err(format!("Positions must be between 1 and {len} for challenge {}.", challenge.name()).into());
+    }
+
+    let mut removed = 0;
+    for id in &ids[from - 1..to] {
+        removed += sqlx::query("DELETE FROM prompts WHERE rowid = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+    }
+
+    let remaining: Vec<i64> = ids[..from - 1].iter().chain(&ids[to..]).copied().collect();
+    if !reorder_queue(&mut tx, &remaining).await? { return Err("Failed to reorder the remaining prompts.".into()); }
+    tx.commit().await?;
+    Ok(removed)
+}
+
+/// Delete every prompt in a given queue. Returns the number of prompts deleted.
+pub async fn clear_prompts(challenge: Challenge) -> ResT<u64> {
+    sqlx::query("DELETE FROM prompts WHERE challenge = ?")
+        .bind(challenge.raw())
         .execute(pool())
         .await
-        .map(|r| r.rows_affected() > 0)
+        .map(|r| r.rows_affected())
         .map_err(|e| e.into())
 }
 
 /// Replaces the prompt with given id with the data specified. Returns whether the operation was successful.
 pub async fn edit_prompt(id: i64, prompt_data: &PromptData) -> ResT<bool> {
-    sqlx::query("UPDATE prompts SET challenge = ?, prompt_string = ?, size_percentage = ?, custom_duration = ?, is_special = ?, extra_announcement_text = ? WHERE rowid = ?")
+    edit_prompt_with(pool(), id, prompt_data).await
+}
+
+/// [`edit_prompt`], but using a specific executor, e.g. a transaction.
+async fn edit_prompt_with<'e, E: Executor<'e, Database = Sqlite>>(executor: E, id: i64, prompt_data: &PromptData) -> ResT<bool> {
+    sqlx::query("UPDATE prompts SET challenge = ?, prompt_string = ?, size_percentage = ?, custom_duration = ?, is_special = ?, extra_announcement_text = ?, skipped = ?, fixed_start_time = ? WHERE rowid = ?")
         .bind(prompt_data.challenge.raw())
         .bind(&prompt_data.prompt_string)
         .bind(prompt_data.size_percentage.map(|x| x as i32))
         .bind(prompt_data.custom_duration.map(|x| x as i32))
         .bind(prompt_data.is_special)
         .bind(&prompt_data.extra_announcement_text)
+        .bind(prompt_data.skipped)
+        .bind(prompt_data.fixed_start_time)
         .bind(id)
-        .execute(pool())
+        .execute(executor)
         .await
         .map(|r| r.rows_affected() > 0)
         .map_err(|e| e.into())
@@ -427,7 +1050,7 @@ pub async fn edit_prompt(id: i64, prompt_data: &PromptData) -> ResT<bool> {
 /// Get the id in the db table of the nth prompt in a given queue.
 pub async fn get_prompt_id(challenge: Challenge, position: usize) -> ResT<i64> {
     if position < 1 { return Err("Invalid position value.".into()); }
-    sqlx::query_as("SELECT rowid FROM prompts WHERE challenge = ? ORDER BY rowid ASC LIMIT ?")
+    sqlx::query_as("SELECT rowid FROM prompts WHERE challenge = ? ORDER BY position ASC, rowid ASC LIMIT ?")
         .bind(challenge.raw())
         .bind(position as i64)
         .fetch_all(pool())
@@ -443,6 +1066,16 @@ pub async fn get_prompt_data(challenge: Challenge, position: usize) -> ResT<Prom
     .cloned().ok_or(format!("There is no prompt at position {position} in challenge {}.", challenge.name()).into())
 }
 
+/// Get the first prompt in a given queue that isn't skipped, together with its position and id.
+pub async fn get_next_prompt(challenge: Challenge) -> ResT<(usize, i64, PromptData)> {
+    let mut tx = pool().begin().await?;
+    let ids = get_prompt_ids_with(&mut *tx, challenge).await?;
+    let prompts = get_prompts_with(&mut *tx, challenge).await?;
+    ids.into_iter().zip(prompts).enumerate().find(|(_, (_, p))| !p.skipped)
+        .map(|(idx, (id, p))| (idx + 1, id, p))
+        .ok_or(format!("There is no prompt that isn't skipped in challenge {}.", challenge.name()).into())
+}
+
 /// Get the id and data of the nth prompt in a given queue
 pub async fn get_prompt_id_data(challenge: Challenge, position: usize) -> ResT<(i64,PromptData)> {
     Ok((get_prompt_id(challenge, position).await?, get_prompt_data(challenge, position).await?))
@@ -450,8 +1083,73 @@ pub async fn get_prompt_id_data(challenge: Challenge, position: usize) -> ResT<(
 
 /// Get all prompts for a challenge, together with their ids in the db table.
 pub async fn get_prompts(challenge: Challenge) -> ResT<Vec<PromptData>> {
-    sqlx::query_as("SELECT * FROM prompts WHERE challenge = ? ORDER BY rowid ASC")
+    get_prompts_with(pool(), challenge).await
+}
+
+/// [`get_prompts`], but using a specific executor, e.g. a transaction.
+async fn get_prompts_with<'e, E: Executor<'e, Database = Sqlite>>(executor: E, challenge: Challenge) -> ResT<Vec<PromptData>> {
+    sqlx::query_as("SELECT * FROM prompts WHERE challenge = ? ORDER BY position ASC, rowid ASC")
         .bind(challenge.raw())
+        .fetch_all(executor)
+        .await
+        .map_err(|e| e.into())
+}
+
+/// Get the ids in the db table of all prompts in a given queue, in queue order.
+async fn get_prompt_ids_with<'e, E: Executor<'e, Database = Sqlite>>(executor: E, challenge: Challenge) -> ResT<Vec<i64>> {
+    sqlx::query_scalar("SELECT rowid FROM prompts WHERE challenge = ? ORDER BY position ASC, rowid ASC")
+        .bind(challenge.raw())
+        .fetch_all(executor)
+        .await
+        .map_err(|e| e.into())
+}
+
+/// Store a reordered queue by numbering the prompts with the given ids in order.
+/// Returns whether the operation was successful.
+async fn reorder_queue(tx: &mut Transaction<'_, Sqlite>, ids: &[i64]) -> ResT<bool> {
+    let mut successful = true;
+    for (idx, id) in ids.iter().enumerate() {
+        successful &= sqlx::query("UPDATE prompts SET position = ? WHERE rowid = ?")
+            .bind(idx as i64 + 1)
+            .bind(id)
+            .execute(&mut **tx)
+            .await?
+            .rows_affected() > 0;
+    }
+    Ok(successful)
+}
+
+/// Move the nth prompt in a given queue into another position, shifting the prompts in
+/// between along by one. Returns whether the operation was successful.
+pub async fn move_prompt(challenge: Challenge, from: usize, to: usize) -> ResT<bool> {
+    let mut tx = pool().begin().await?;
+    let mut ids = get_prompt_ids_with(&mut *tx, challenge).await?;
+    let len = ids.len();
+    if !(1..=len).contains(&from) || !(1..=len).contains(&to) {
+        return Err(format!("Positions must be between 1 and {len} for challenge {}.", challenge.name()).into());
+    }
+
+    let id = ids.remove(from - 1);
+    ids.insert(to - 1, id);
+    if !reorder_queue(&mut tx, &ids).await? { return Ok(false); }
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Put the prompts in a given queue into a random order. Returns whether the operation was successful.
+pub async fn shuffle_prompts(challenge: Challenge, rng: &mut StdRng) -> ResT<bool> {
+    let mut tx = pool().begin().await?;
+    let mut ids = get_prompt_ids_with(&mut *tx, challenge).await?;
+    ids.shuffle(rng);
+    if !reorder_queue(&mut tx, &ids).await? { return Ok(false); }
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Get the prompts of all weeks of a challenge that are in the database, along with their week numbers.
+pub async fn get_used_prompts(challenge: Challenge) -> ResT<Vec<(i64, String)>> {
+    sqlx::query_as("SELECT week_num, prompt_string FROM weeks WHERE challenge = ? ORDER BY week_num ASC")
+        .bind(challenge.raw() as i64)
         .fetch_all(pool())
         .await
         .map_err(|e| e.into())
@@ -459,11 +1157,27 @@ pub async fn get_prompts(challenge: Challenge) -> ResT<Vec<PromptData>> {
 
 /// Get stats for a week.
 pub async fn get_week_info(week_num: i64, challenge: Challenge) -> ResT<WeekInfo> {
+    get_week_info_with(pool(), week_num, challenge).await
+}
+
+/// Get finished weeks of a challenge, most recent first. A negative `limit` means no limit.
+pub async fn get_weeks(challenge: Challenge, limit: i64, offset: i64) -> ResT<Vec<WeekInfo>> {
+    sqlx::query_as("SELECT * FROM weeks WHERE challenge = ? AND actual_end_time IS NOT NULL ORDER BY week_num DESC LIMIT ? OFFSET ?")
+        .bind(challenge.raw() as i64)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool())
+        .await
+        .map_err(|e| e.into())
+}
+
+/// [`get_week_info`], but using a specific executor, e.g. a transaction.
+async fn get_week_info_with<'e, E: Executor<'e, Database = Sqlite>>(executor: E, week_num: i64, challenge: Challenge) -> ResT<WeekInfo> {
     sqlx::query_as(
         r#"SELECT * FROM weeks WHERE week_num = ? AND challenge = ? LIMIT 1; "#)
         .bind(week_num)
         .bind(challenge.raw() as i64)
-        .fetch_optional(pool())
+        .fetch_optional(executor)
         .await
         .map_err(|e| e.to_string())
         .map(|x| x.ok_or(format!("There is no week {week_num} for challenge {challenge:?} in the database.").into()))?
@@ -471,11 +1185,16 @@ pub async fn get_week_info(week_num: i64, challenge: Challenge) -> ResT<WeekInfo
 
 /// Inserts a week into the db or modifies it if it's already there.
 pub async fn insert_or_modify_week(week_info: WeekInfo) -> Res {
+    insert_or_modify_week_with(pool(), week_info).await
+}
+
+/// [`insert_or_modify_week`], but using a specific executor, e.g. a transaction.
+async fn insert_or_modify_week_with<'e, E: Executor<'e, Database = Sqlite>>(executor: E, week_info: WeekInfo) -> Res {
     // there must be a better way to do this
     // like surely
     sqlx::query(r#"
-    INSERT INTO weeks (week_num, challenge, prompt_string, size_percentage, target_start_time, target_end_time, actual_start_time, actual_end_time, is_special, num_subs, poll_message_id, second_poll_message_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
-    ON CONFLICT (week_num, challenge) DO UPDATE SET (prompt_string, size_percentage, target_start_time, target_end_time, actual_start_time, actual_end_time, is_special, num_subs, poll_message_id, second_poll_message_id) = (?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12);
+    INSERT INTO weeks (week_num, challenge, prompt_string, size_percentage, target_start_time, target_end_time, actual_start_time, actual_end_time, is_special, num_subs, poll_message_ids, target_voting_end_time, actual_voting_end_time) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+    ON CONFLICT (week_num, challenge) DO UPDATE SET (prompt_string, size_percentage, target_start_time, target_end_time, actual_start_time, actual_end_time, is_special, num_subs, poll_message_ids, target_voting_end_time, actual_voting_end_time) = (?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13);
 "#)
         .bind(week_info.week_num)
         .bind(week_info.challenge.raw() as i64)
@@ -487,97 +1206,721 @@ pub async fn insert_or_modify_week(week_info: WeekInfo) -> Res {
         .bind(week_info.actual_end_time.0.map(|x| x.timestamp()))
         .bind(week_info.is_special)
         .bind(week_info.num_subs)
-        .bind(week_info.poll_message_id.0.map(|x| x.get() as i64))
-        .bind(week_info.second_poll_message_id.0.map(|x| x.get() as i64))
-        .execute(pool())
+        .bind(week_info.poll_message_ids.to_json())
+        .bind(week_info.target_voting_end_time.0.map(|x| x.timestamp()))
+        .bind(week_info.actual_voting_end_time.0.map(|x| x.timestamp()))
+        .execute(executor)
         .await
         .map(|_| ())
         .map_err(|e| e.into())
 }
 
-/// Updates the `votes` table with one user's vote. Returns whether the operation was successful.
+/// Toggles one user's vote for a submission. Returns whether the operation was successful.
+/// Fails with a [`VoteLimitError`] if adding the vote would exceed the configured `max_votes_per_user`.
 pub async fn register_vote(challenge: Challenge, week_num: i64, user_id: UserId, sub_num: i64) -> ResT<bool> {
-    let mut votes: i64 = sqlx::query_scalar("SELECT votes FROM votes WHERE challenge = ? AND week_num = ? AND user = ? LIMIT 1")
+    register_vote_with(pool(), challenge, week_num, user_id, sub_num).await
+}
+
+/// [`register_vote`], but using a specific connexion pool.
+async fn register_vote_with(pool: &SqlitePool, challenge: Challenge, week_num: i64, user_id: UserId, sub_num: i64) -> ResT<bool> {
+    let mut tx = pool.begin().await?;
+    let removed = sqlx::query("DELETE FROM vote_entries WHERE challenge = ? AND week_num = ? AND user = ? AND submission_index = ?")
         .bind(challenge.raw() as i16)
         .bind(week_num)
         .bind(user_id.get() as i64)
-        .fetch_optional(pool())
+        .bind(sub_num)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected() > 0;
+    if !removed {
+        if let Some(limit) = config().max_votes_per_user {
+            let used: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM vote_entries WHERE challenge = ? AND week_num = ? AND user = ?")
+                .bind(challenge.raw() as i16)
+                .bind(week_num)
+                .bind(user_id.get() as i64)
+                .fetch_one(&mut *tx)
+                .await?;
+            if used >= limit { return Err(VoteLimitError { used, limit }.into()); }
+        }
+        sqlx::query("INSERT INTO vote_entries (challenge, week_num, user, submission_index) VALUES (?, ?, ?, ?)")
+            .bind(challenge.raw() as i16)
+            .bind(week_num)
+            .bind(user_id.get() as i64)
+            .bind(sub_num)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Reads all the votes from a user for a particular challenge and week, as a sorted list of submission indices.
+pub async fn get_votes(challenge: Challenge, week_num: i64, user_id: UserId, num_subs: i64) -> ResT<Vec<i64>> {
+    get_votes_with(pool(), challenge, week_num, user_id, num_subs).await
+}
+
+/// [`get_votes`], but using a specific executor, e.g. a transaction.
+async fn get_votes_with<'e, E: Executor<'e, Database = Sqlite>>(executor: E, challenge: Challenge, week_num: i64, user_id: UserId, num_subs: i64) -> ResT<Vec<i64>> {
+    sqlx::query_scalar("SELECT submission_index FROM vote_entries WHERE challenge = ? AND week_num = ? AND user = ? AND submission_index < ? ORDER BY submission_index ASC")
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .bind(user_id.get() as i64)
+        .bind(num_subs)
+        .fetch_all(executor)
         .await
-        .map_err(|e| e.to_string())?
-        .unwrap_or(0);
-    votes ^= (1 << sub_num);
-    sqlx::query(r#"INSERT INTO votes (challenge, week_num, user, votes) VALUES (?1, ?2, ?3, ?4)
-        ON CONFLICT (challenge, week_num, user) DO UPDATE SET votes = ?4;"#,)
+        .map_err(|e| e.into())
+}
+
+/// Remove all of a user's votes for a particular challenge and week. Returns how many votes were removed.
+pub async fn clear_votes(challenge: Challenge, week_num: i64, user_id: UserId) -> ResT<u64> {
+    sqlx::query("DELETE FROM vote_entries WHERE challenge = ? AND week_num = ? AND user = ?")
         .bind(challenge.raw() as i16)
         .bind(week_num)
         .bind(user_id.get() as i64)
-        .bind(votes)
         .execute(pool())
         .await
-        .map(|r| r.rows_affected() > 0)
+        .map(|r| r.rows_affected())
         .map_err(|e| e.into())
 }
 
-/// Reads all the votes from a user for a particular challenge and week. Processes the bitstring into an actual list.
-pub async fn get_votes(challenge: Challenge, week_num: i64, user_id: UserId, num_subs: i64) -> ResT<Vec<i64>> {
-    let votes: i64 = sqlx::query_scalar("SELECT votes FROM votes WHERE challenge = ? AND week_num = ? AND user = ? LIMIT 1")
+/// Get every vote cast in a given week, as pairs of voter and submission index.
+pub async fn get_vote_entries(challenge: Challenge, week_num: i64) -> ResT<Vec<(UserId, i64)>> {
+    sqlx::query_as("SELECT user, submission_index FROM vote_entries WHERE challenge = ? AND week_num = ?")
         .bind(challenge.raw() as i16)
         .bind(week_num)
-        .bind(user_id.get() as i64)
-        .fetch_optional(pool())
+        .fetch_all(pool())
         .await
-        .map_err(|e| e.to_string())?
-        .unwrap_or(0);
-    Ok((0..num_subs).filter(|x| (1 << x) & votes != 0).collect())
+        .map(|x: Vec<(i64, i64)>| x.into_iter().map(|(user, idx)| (UserId::new(user as u64), idx)).collect())
+        .map_err(|e| e.into())
 }
 
-/// Do the necessary database operations to mark a week as completed.
-pub async fn end_week(challenge: Challenge, week_num: i64, end_time: Timestamp) -> Res {
-    let mut current_week_info = get_week_info(week_num, challenge).await?;
+/// Count how many submissions each user voted for in a given week, sorted by descending count.
+pub async fn count_votes_per_user(challenge: Challenge, week_num: i64) -> ResT<Vec<(UserId, i64)>> {
+    sqlx::query_as(r#"
+        SELECT user, COUNT(*) AS total FROM vote_entries
+        WHERE challenge = ? AND week_num = ?
+        GROUP BY user
+        ORDER BY total DESC, user ASC;
+    "#)
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .fetch_all(pool())
+        .await
+        .map(|x: Vec<(i64, i64)>| x.into_iter().map(|(user, total)| (UserId::new(user as u64), total)).collect())
+        .map_err(|e| e.into())
+}
+
+/// Count how many users voted in a given week.
+pub async fn count_voters(challenge: Challenge, week_num: i64) -> ResT<i64> {
+    sqlx::query_scalar("SELECT COUNT(DISTINCT user) FROM vote_entries WHERE challenge = ? AND week_num = ?")
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .fetch_one(pool())
+        .await
+        .map_err(|e| e.into())
+}
+
+/// Check whether voting on a week's submissions has concluded. Votes for a week are accepted
+/// until its own voting window closes or, if it doesn't have one, until the week after it ends.
+pub async fn voting_concluded(challenge: Challenge, week_num: i64) -> ResT<bool> {
+    Ok(get_week_info(week_num, challenge).await?.actual_voting_end_time != NULL_TIMESTAMP)
+}
+
+/// Get the id of the message announcing a week, if it has been posted.
+pub async fn get_announcement_message(challenge: Challenge, week_num: i64) -> ResT<Option<MessageId>> {
+    sqlx::query_scalar("SELECT announcement_message_id FROM weeks WHERE challenge = ? AND week_num = ?;")
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .fetch_one(pool())
+        .await
+        .map(|id: Option<i64>| id.map(|id| MessageId::new(id as u64)))
+        .map_err(|e| e.into())
+}
+
+/// Record the id of the message announcing a week.
+pub async fn set_announcement_message(challenge: Challenge, week_num: i64, message: MessageId) -> Res {
+    sqlx::query("UPDATE weeks SET announcement_message_id = ? WHERE challenge = ? AND week_num = ?;")
+        .bind(message.get() as i64)
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .execute(pool())
+        .await
+        .map(|_| ())
+        .map_err(|e| e.into())
+}
+
+/// Check whether the deadline reminder for a week has been posted.
+pub async fn reminder_sent(challenge: Challenge, week_num: i64) -> ResT<bool> {
+    sqlx::query_scalar("SELECT reminder_sent FROM weeks WHERE challenge = ? AND week_num = ?;")
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .fetch_one(pool())
+        .await
+        .map_err(|e| e.into())
+}
+
+/// Record that the deadline reminder for a week has been posted.
+pub async fn set_reminder_sent(challenge: Challenge, week_num: i64) -> Res {
+    sqlx::query("UPDATE weeks SET reminder_sent = 1 WHERE challenge = ? AND week_num = ?;")
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .execute(pool())
+        .await
+        .map(|_| ())
+        .map_err(|e| e.into())
+}
+
+/// Get the weeks whose voting window has closed but whose voting hasn't been concluded yet.
+pub async fn get_weeks_with_voting_due(challenge: Challenge, current_time: Timestamp) -> ResT<Vec<i64>> {
+    sqlx::query_scalar(r#"
+        SELECT week_num FROM weeks
+        WHERE challenge = ? AND actual_voting_end_time IS NULL
+        AND target_voting_end_time IS NOT NULL AND target_voting_end_time < ?
+        ORDER BY week_num ASC;
+    "#)
+        .bind(challenge.raw() as i64)
+        .bind(current_time.0.map(|x| x.timestamp()))
+        .fetch_all(pool())
+        .await
+        .map_err(|e| e.into())
+}
+
+/// Tally the votes for a week, returning each submission along with its author and the number
+/// of votes it received, sorted by descending vote count. Ties are broken in favour of the
+/// earliest submission, by the time it was registered and then by message order, so that
+/// placements are always the same for the same votes.
+///
+/// Votes refer to submissions by their index in the order returned by [`get_submissions`],
+/// so the submissions are numbered using the same ordering here.
+pub async fn tally_week(challenge: Challenge, week_num: i64) -> ResT<Vec<(MessageId, UserId, i64)>> {
+    tally_week_with(pool(), challenge, week_num).await
+}
+
+/// Like [`tally_week`], but runs on the given executor.
+async fn tally_week_with<'e, E: Executor<'e, Database = Sqlite>>(executor: E, challenge: Challenge, week_num: i64) -> ResT<Vec<(MessageId, UserId, i64)>> {
+    sqlx::query_as(
+        r#"
+        WITH subs AS (
+            SELECT message, author, time, ROW_NUMBER() OVER (ORDER BY message ASC) - 1 AS idx
+            FROM submissions
+            WHERE challenge = ?1 AND week_num = ?2
+        )
+        SELECT subs.message, subs.author, COUNT(vote_entries.user) AS total
+        FROM subs
+        LEFT JOIN vote_entries
+            ON vote_entries.challenge = ?1
+            AND vote_entries.week_num = ?2
+            AND vote_entries.submission_index = subs.idx
+        GROUP BY subs.message, subs.author, subs.time
+        ORDER BY total DESC, subs.time ASC, subs.message ASC;
+    "#)
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .fetch_all(executor)
+        .await
+        .map_err(|e| e.into())
+        .map(|x| x.into_iter().map(|(m, a, t): (i64, i64, i64)| (MessageId::new(m as u64), UserId::new(a as u64), t)).collect())
+}
+
+/// Rank the submissions of a week from its [`tally`](tally_week): users with several submissions
+/// are only ranked by their best one, and submissions without any votes aren't ranked at all.
+/// The placement of each remaining submission is its index in the result plus one.
+pub fn rank_winners(tally: Vec<(MessageId, UserId, i64)>) -> Vec<(MessageId, UserId, i64)> {
+    let mut winners: Vec<(MessageId, UserId, i64)> = vec![];
+    for (message, author, total) in tally {
+        // the tally is sorted from best to worst, so nothing after this has any votes either
+        if total == 0 { break; }
+        if !winners.iter().any(|(_, user, _)| *user == author) {
+            winners.push((message, author, total));
+        }
+    }
+    winners
+}
+
+/// Do the necessary database operations to mark a week as completed. Unless the week before it
+/// has its own voting window, this also concludes the voting for that week (see [`end_voting`]).
+/// Returns whether it did.
+pub async fn end_week(challenge: Challenge, week_num: i64, end_time: Timestamp) -> ResT<bool> {
+    end_week_with(pool(), challenge, week_num, end_time).await
+}
+
+/// [`end_week`], but using a specific connexion pool.
+async fn end_week_with(pool: &SqlitePool, challenge: Challenge, week_num: i64, end_time: Timestamp) -> ResT<bool> {
+    let mut tx = pool.begin().await?;
+    let mut current_week_info = get_week_info_with(&mut *tx, week_num, challenge).await?;
     current_week_info.actual_end_time = end_time;
-    insert_or_modify_week(current_week_info).await?;
+    insert_or_modify_week_with(&mut *tx, current_week_info).await?;
+
+    // There is no previous week before the first one.
+    let concluded = match get_week_info_with(&mut *tx, week_num - 1, challenge).await {
+        Ok(previous_week_info) if previous_week_info.target_voting_end_time == NULL_TIMESTAMP
+            && previous_week_info.actual_voting_end_time == NULL_TIMESTAMP => {
+            conclude_voting_with(&mut tx, previous_week_info, end_time).await?;
+            true
+        }
+        _ => false,
+    };
+    tx.commit().await?;
+    Ok(concluded)
+}
+
+/// Conclude the voting for a week: its vote totals are stored in the submissions table
+/// and its placements are added to the users’ profiles.
+pub async fn end_voting(challenge: Challenge, week_num: i64, end_time: Timestamp) -> Res {
+    let mut tx = pool().begin().await?;
+    let week_info = get_week_info_with(&mut *tx, week_num, challenge).await?;
+    conclude_voting_with(&mut tx, week_info, end_time).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// [`end_voting`], but as part of a larger transaction.
+async fn conclude_voting_with(tx: &mut Transaction<'_, Sqlite>, mut week_info: WeekInfo, end_time: Timestamp) -> Res {
+    store_vote_totals_with(&mut **tx, week_info.challenge, week_info.week_num).await?;
+    record_winners_with(tx, week_info.challenge, week_info.week_num).await?;
+    week_info.actual_voting_end_time = end_time;
+    insert_or_modify_week_with(&mut **tx, week_info).await
+}
+
+/// Count the submissions for a week, using a specific executor, e.g. a transaction.
+async fn count_submissions_with<'e, E: Executor<'e, Database = Sqlite>>(executor: E, challenge: Challenge, week_num: i64) -> ResT<i64> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM submissions WHERE challenge = ? AND week_num = ?")
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .fetch_one(executor)
+        .await
+        .map_err(|e| e.into())
+}
+
+/// Record that the poll for a week has been posted ahead of the rollover, and when voting on it closes.
+/// The number of submissions is counted as part of the same transaction.
+pub async fn open_voting(challenge: Challenge, week_num: i64, poll_message_ids: Vec<MessageId>, target_voting_end_time: Timestamp) -> Res {
+    let mut tx = pool().begin().await?;
+    let mut week_info = get_week_info_with(&mut *tx, week_num, challenge).await?;
+    week_info.num_subs = count_submissions_with(&mut *tx, challenge, week_num).await?;
+    week_info.poll_message_ids = poll_message_ids.into();
+    week_info.target_voting_end_time = target_voting_end_time;
+    insert_or_modify_week_with(&mut *tx, week_info).await?;
+    tx.commit().await?;
     Ok(())
 }
-/// Do the necessary database operations to initialise a new week.
-pub async fn initialise_week(challenge: Challenge, week_num: i64, prompt: &PromptData, target_start_time: Timestamp, target_end_time: Timestamp) -> Res {
+
+/// Write the number of votes each submission of a week received into `submissions.votes`.
+async fn store_vote_totals_with<'e, E: Executor<'e, Database = Sqlite>>(executor: E, challenge: Challenge, week_num: i64) -> Res {
+    sqlx::query(
+        r#"
+        WITH subs AS (
+            SELECT message, ROW_NUMBER() OVER (ORDER BY message ASC) - 1 AS idx
+            FROM submissions
+            WHERE challenge = ?1 AND week_num = ?2
+        )
+        UPDATE submissions SET votes = (
+            SELECT COUNT(*) FROM vote_entries, subs
+            WHERE subs.message = submissions.message
+            AND vote_entries.challenge = ?1
+            AND vote_entries.week_num = ?2
+            AND vote_entries.submission_index = subs.idx
+        )
+        WHERE challenge = ?1 AND week_num = ?2;
+    "#)
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .execute(executor)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.into())
+}
+
+/// Overwrite the stored vote total of a submission, e.g. to import historical results. This only
+/// changes `submissions.votes`; the individual votes in `vote_entries`, which polls, vote summaries
+/// and placements are computed from, are left alone. Returns whether the submission exists.
+pub async fn set_submission_votes(challenge: Challenge, week_num: i64, message: MessageId, votes: i64) -> ResT<bool> {
+    sqlx::query("UPDATE submissions SET votes = ? WHERE challenge = ? AND week_num = ? AND message = ?")
+        .bind(votes)
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .bind(message.get() as i64)
+        .execute(pool())
+        .await
+        .map(|r| r.rows_affected() > 0)
+        .map_err(|e| e.into())
+}
+
+/// Add the placements of a week whose voting has concluded to the profiles of its participants.
+///
+/// Placements are as given by [`rank_winners`], so ties go to the earliest submission.
+/// This does nothing if the winners of the week have already been recorded.
+async fn record_winners_with(tx: &mut Transaction<'_, Sqlite>, challenge: Challenge, week_num: i64) -> Res {
+    let recorded: Option<bool> = sqlx::query_scalar("SELECT winners_recorded FROM weeks WHERE challenge = ? AND week_num = ?;")
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .fetch_optional(&mut **tx)
+        .await?;
+
+    // Nothing to do if the week doesn’t exist (e.g. before the first week) or is already done.
+    if recorded != Some(false) { return Ok(()); }
+
+    let (first, second, third, highest) = match challenge {
+        Challenge::Glyph => ("glyphs_first", "glyphs_second", "glyphs_third", "highest_ranking_glyphs"),
+        Challenge::Ambigram => ("ambigrams_first", "ambigrams_second", "ambigrams_third", "highest_ranking_ambigrams"),
+    };
+
+    let placements = rank_winners(tally_week_with(&mut **tx, challenge, week_num).await?).into_iter()
+        .enumerate()
+        .map(|(i, (_, author, _))| (author, i as i64 + 1));
+
+    for (user, place) in placements {
+        let column = match place {
+            1 => Some(first),
+            2 => Some(second),
+            3 => Some(third),
+            _ => None,
+        };
+
+        sqlx::query("INSERT INTO users (id) VALUES (?) ON CONFLICT (id) DO NOTHING;")
+            .bind(user.get() as i64)
+            .execute(&mut **tx)
+            .await?;
+
+        let increment = column.map(|c| format!(", {c} = {c} + 1")).unwrap_or_default();
+        let query = format!(r#"
+            UPDATE users SET
+                {highest} = CASE WHEN {highest} = 0 OR {highest} > ?1 THEN ?1 ELSE {highest} END
+                {increment}
+            WHERE id = ?2;
+        "#);
+        sqlx::query(&query)
+            .bind(place)
+            .bind(user.get() as i64)
+            .execute(&mut **tx)
+            .await?;
+    }
+
+    sqlx::query("UPDATE weeks SET winners_recorded = 1 WHERE challenge = ? AND week_num = ?;")
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Do the necessary database operations to initialise a new week from the prompt with id `prompt_id`.
+/// The id is remembered so that exactly that prompt is removed from the queue when the week starts.
+pub async fn initialise_week(challenge: Challenge, week_num: i64, prompt_id: i64, prompt: &PromptData, target_start_time: Timestamp, target_end_time: Timestamp) -> Res {
+    initialise_week_with(pool(), challenge, week_num, prompt_id, prompt, target_start_time, target_end_time).await
+}
+
+/// [`initialise_week`], but using a specific connexion pool.
+async fn initialise_week_with(pool: &SqlitePool, challenge: Challenge, week_num: i64, prompt_id: i64, prompt: &PromptData, target_start_time: Timestamp, target_end_time: Timestamp) -> Res {
     let week_info = WeekInfo { challenge, week_num, prompt_string: prompt.prompt_string.clone(), size_percentage: prompt.size_percentage.unwrap_or(100),
         target_start_time, target_end_time, actual_start_time: None.into(), actual_end_time: None.into(),
-        is_special: prompt.is_special.unwrap_or(false), num_subs: 0, poll_message_id: None.into(), second_poll_message_id: None.into()};
-    insert_or_modify_week(week_info).await?;
+        is_special: prompt.is_special.unwrap_or(false), num_subs: 0, poll_message_ids: Default::default(),
+        target_voting_end_time: None.into(), actual_voting_end_time: None.into()};
+    let mut tx = pool.begin().await?;
+    insert_or_modify_week_with(&mut *tx, week_info).await?;
+    sqlx::query("UPDATE weeks SET prompt_id = ? WHERE challenge = ? AND week_num = ?;")
+        .bind(prompt_id)
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
     Ok(())
 }
-/// Do the necessary database operations to roll over to next week.
+
+/// Get the id of the prompt a week was initialised from. This is `None` for weeks that
+/// were initialised before this was recorded.
+pub async fn get_week_prompt_id(challenge: Challenge, week_num: i64) -> ResT<Option<i64>> {
+    sqlx::query_scalar("SELECT prompt_id FROM weeks WHERE challenge = ? AND week_num = ?;")
+        .bind(challenge.raw() as i16)
+        .bind(week_num)
+        .fetch_one(pool())
+        .await
+        .map_err(|e| e.into())
+}
+/// Do the necessary database operations to roll over to next week. This happens in a
+/// single transaction, so either all of them take effect or none of them do. The number of
+/// submissions for the week that just ended is counted as part of it too.
 pub async fn rollover_week(challenge: Challenge, current_week_num: i64, current_time: Timestamp, 
-        num_subs: i64, poll_message_id: MessageId, second_poll_message_id: Option<MessageId>) -> Res {
-    let mut current_week_info = get_week_info(current_week_num, challenge).await?;
-    let mut next_week_info = get_week_info(current_week_num + 1, challenge).await?;
-    current_week_info.poll_message_id = Some(poll_message_id).into();
-    current_week_info.second_poll_message_id = second_poll_message_id.into();
-    current_week_info.num_subs = num_subs;
+        poll_message_ids: Vec<MessageId>, target_voting_end_time: Timestamp) -> Res {
+    rollover_week_with(pool(), challenge, current_week_num, current_time, poll_message_ids, target_voting_end_time).await
+}
+
+/// [`rollover_week`], but using a specific connexion pool.
+async fn rollover_week_with(pool: &SqlitePool, challenge: Challenge, current_week_num: i64, current_time: Timestamp,
+        poll_message_ids: Vec<MessageId>, target_voting_end_time: Timestamp) -> Res {
+    let mut tx = pool.begin().await?;
+    let mut current_week_info = get_week_info_with(&mut *tx, current_week_num, challenge).await?;
+    let mut next_week_info = get_week_info_with(&mut *tx, current_week_num + 1, challenge).await?;
+    current_week_info.poll_message_ids = poll_message_ids.into();
+    current_week_info.num_subs = count_submissions_with(&mut *tx, challenge, current_week_num).await?;
+    current_week_info.target_voting_end_time = target_voting_end_time;
     next_week_info.actual_start_time = current_time;
-    insert_or_modify_week(current_week_info).await?;
-    insert_or_modify_week(next_week_info).await?;
-    set_current_week_num(challenge, current_week_num + 1).await?;
+    insert_or_modify_week_with(&mut *tx, current_week_info).await?;
+    insert_or_modify_week_with(&mut *tx, next_week_info).await?;
+    set_current_week_num_with(&mut *tx, challenge, current_week_num + 1).await?;
+
+    // Dropping the transaction without committing it rolls it back, so
+    // any of the above failing leaves the database untouched.
+    tx.commit().await?;
     Ok(())
 }
 
 
 /// For a prompt in any queue, forecast based on current parameters when that prompt will be used and
 /// what the week number will be. Allows for accurate image preview. Takes negative index.
-pub async fn forecast_prompt_details(challenge: Challenge, mut position: i64) -> ResT<(i64, Timestamp, Timestamp)> {
+pub async fn forecast_prompt_details(challenge: Challenge, position: i64) -> ResT<(i64, Timestamp, Timestamp)> {
     let queue = get_prompts(challenge).await?;
     info!("{:?}", queue);
+    forecast_queue_details(challenge, &queue, position).await
+}
+
+/// [`forecast_prompt_details`], but for a queue that is already in memory, e.g. because
+/// the caller has just modified it.
+pub async fn forecast_queue_details(challenge: Challenge, queue: &[PromptData], mut position: i64) -> ResT<(i64, Timestamp, Timestamp)> {
     if position < 0 {
         position += queue.len() as i64 + 1;
     }
-    let prompt = queue.get((position as usize).checked_sub(1).ok_or::<Error>("0 is not a valid prompt position.".into())?)
-    .ok_or::<Error>(format!("There is no prompt at position {position} in challenge {}.", challenge.name()).into())?;
-    let mut week_num = get_current_week_num(challenge).await?;
-    let current_week_info = get_week_info(week_num, challenge).await?;
-    let mut start_time = current_week_info.target_end_time;
-    for pos in 1..position {
-        start_time += challenge.default_duration() * queue[(pos as usize) - 1].custom_duration.unwrap_or(1) as i32;
+    let idx = (position as usize).checked_sub(1).ok_or::<Error>("0 is not a valid prompt position.".into())?;
+    if idx >= queue.len() {
+        return Err(format!("There is no prompt at position {position} in challenge {}.", challenge.name()).into());
+    }
+    Ok(forecast_queue(challenge, &queue[..=idx]).await?[idx])
+}
+
+/// Forecast the week number and start and end time of every prompt in a queue that is already
+/// in memory, in queue order. This mirrors how the scheduler initialises weeks: each week starts
+/// the configured time gap after the previous one ends, and ends that long before its full duration is up.
+/// Prompts with a fixed start time don't start before it. Skipped prompts don't take up a week; they
+/// are forecast for the week they would get if unskipped.
+pub async fn forecast_queue(challenge: Challenge, queue: &[PromptData]) -> ResT<Vec<(i64, Timestamp, Timestamp)>> {
+    forecast_queue_with(pool(), challenge, queue).await
+}
+
+/// [`forecast_queue`], but using a specific connexion pool.
+async fn forecast_queue_with(pool: &SqlitePool, challenge: Challenge, queue: &[PromptData]) -> ResT<Vec<(i64, Timestamp, Timestamp)>> {
+    let mut week_num = get_current_week_num_with(pool, challenge).await?;
+    let current_week_info = get_week_info_with(pool, week_num, challenge).await?;
+    let mut end_time = current_week_info.target_end_time;
+    let mut forecast = Vec::with_capacity(queue.len());
+    for prompt in queue {
+        let start_time = prompt.start_time_after(end_time);
+        let prompt_end_time = start_time + challenge.default_duration() * (prompt.custom_duration.unwrap_or(1) as i32) - config().time_gap;
+        forecast.push((week_num + 1, start_time, prompt_end_time));
+        if !prompt.skipped {
+            week_num += 1;
+            end_time = prompt_end_time;
+        }
+    }
+    Ok(forecast)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    /// An empty in-memory database. It only lives as long as its connexion,
+    /// so the pool keeps exactly one around for as long as it exists.
+    async fn memory_pool() -> SqlitePool {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap()
+    }
+
+    /// An in-memory database with all tables created.
+    async fn test_pool() -> SqlitePool {
+        let pool = memory_pool().await;
+        create_tables(&pool).await;
+        pool
+    }
+
+    #[tokio::test]
+    async fn votes_past_index_63() {
+        let pool = test_pool().await;
+        let user = UserId::new(1);
+        for idx in [100, 0, 64, 63] {
+            assert!(register_vote_with(&pool, Challenge::Glyph, 1, user, idx).await.unwrap());
+        }
+        assert_eq!(get_votes_with(&pool, Challenge::Glyph, 1, user, 101).await.unwrap(), vec![0, 63, 64, 100]);
+
+        // Voting for a submission again takes the vote back.
+        register_vote_with(&pool, Challenge::Glyph, 1, user, 64).await.unwrap();
+        assert_eq!(get_votes_with(&pool, Challenge::Glyph, 1, user, 101).await.unwrap(), vec![0, 63, 100]);
+    }
+
+    #[tokio::test]
+    async fn migrate_vote_bitfields() {
+        let pool = memory_pool().await;
+        sqlx::query("CREATE TABLE votes (challenge INTEGER NOT NULL, week_num INTEGER, user INTEGER, votes INTEGER, PRIMARY KEY(challenge, week_num, user)) STRICT")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        // Bit 63 is the sign bit, so this is where an arithmetic shift could go wrong.
+        sqlx::query("INSERT INTO votes (challenge, week_num, user, votes) VALUES (0, 1, 1, ?)")
+            .bind(i64::MIN | 0b101)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        create_tables(&pool).await;
+        assert!(!has_table(&pool, "votes").await);
+        assert_eq!(get_votes_with(&pool, Challenge::Glyph, 1, UserId::new(1), 64).await.unwrap(), vec![0, 2, 63]);
+    }
+
+    #[tokio::test]
+    async fn reorder_queue_rolls_back() {
+        let pool = test_pool().await;
+        for prompt_string in ["a", "b"] {
+            add_prompt_with(&pool, &test_prompt(prompt_string)).await.unwrap();
+        }
+        let ids = get_prompt_ids_with(&pool, Challenge::Glyph).await.unwrap();
+
+        // Swap the prompts, but delete the first one first, so that moving the
+        // second one succeeds and moving the first one afterwards doesn't.
+        let mut tx = pool.begin().await.unwrap();
+        sqlx::query("DELETE FROM prompts WHERE rowid = ?").bind(ids[0]).execute(&mut *tx).await.unwrap();
+        assert!(!reorder_queue(&mut tx, &[ids[1], ids[0]]).await.unwrap());
+        drop(tx);
+
+        let position: i64 = sqlx::query_scalar("SELECT position FROM prompts WHERE rowid = ?").bind(ids[1]).fetch_one(&pool).await.unwrap();
+        assert_eq!(position, 2);
+        assert_eq!(get_prompt_ids_with(&pool, Challenge::Glyph).await.unwrap(), ids);
+    }
+
+    #[tokio::test]
+    async fn deregister_submission_shifts_votes() {
+        let pool = test_pool().await;
+        for message in [10, 20, 30] {
+            register_submission_with(&pool, MessageId::new(message), Challenge::Glyph, UserId::new(message), "", 1).await.unwrap();
+        }
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        for idx in [0, 1, 2] { register_vote_with(&pool, Challenge::Glyph, 1, first, idx).await.unwrap(); }
+        register_vote_with(&pool, Challenge::Glyph, 1, second, 1).await.unwrap();
+
+        deregister_submission_with(&pool, MessageId::new(10), Challenge::Glyph, 1).await.unwrap();
+        assert_eq!(get_votes_with(&pool, Challenge::Glyph, 1, first, 3).await.unwrap(), vec![0, 1]);
+        assert_eq!(get_votes_with(&pool, Challenge::Glyph, 1, second, 3).await.unwrap(), vec![0]);
+
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM vote_entries").fetch_one(&pool).await.unwrap();
+        assert_eq!(remaining, 3);
+    }
+
+    /// A prompt with the given text and no modifiers.
+    fn test_prompt(prompt_string: &str) -> PromptData {
+        PromptData::new(Challenge::Glyph, prompt_string, None, None, None, None, None).unwrap()
+    }
+
+    /// The time `days` days after the start of 2024.
+    fn day(days: i64) -> Timestamp {
+        Timestamp::try_from(1704067200 + days * 86400).unwrap()
+    }
+
+    #[tokio::test]
+    async fn end_week_stores_vote_totals() {
+        let pool = test_pool().await;
+        initialise_week_with(&pool, Challenge::Glyph, 1, 1, &test_prompt("a"), day(0), day(7)).await.unwrap();
+        initialise_week_with(&pool, Challenge::Glyph, 2, 2, &test_prompt("b"), day(7), day(14)).await.unwrap();
+        for message in [10, 20, 30] {
+            register_submission_with(&pool, MessageId::new(message), Challenge::Glyph, UserId::new(message), "", 1).await.unwrap();
+        }
+        for (user, idx) in [(1, 0), (1, 1), (2, 1), (3, 1)] {
+            register_vote_with(&pool, Challenge::Glyph, 1, UserId::new(user), idx).await.unwrap();
+        }
+
+        // Week 1 has no voting window of its own, so ending week 2 concludes its voting.
+        assert!(end_week_with(&pool, Challenge::Glyph, 2, day(14)).await.unwrap());
+        let votes: Vec<(i64, i64)> = sqlx::query_as("SELECT message, votes FROM submissions WHERE week_num = 1 ORDER BY message ASC")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(votes, vec![(10, 1), (20, 3), (30, 0)]);
+    }
+
+    #[tokio::test]
+    async fn prompt_round_trip() {
+        let pool = test_pool().await;
+        let mut prompt = PromptData::new(Challenge::Glyph, "  a  ", Some(80), Some(2), Some(true), Some("extra".to_owned()), Some("2024-01-08")).unwrap();
+        prompt.skipped = true;
+        assert_eq!(prompt.prompt_string, "a");
+        assert_eq!(prompt.fixed_start_time, Some(1704672000));
+
+        add_prompt_with(&pool, &prompt).await.unwrap();
+        assert_eq!(get_prompts_with(&pool, Challenge::Glyph).await.unwrap(), vec![prompt]);
+    }
+
+    #[tokio::test]
+    async fn forecast_matches_rollovers() {
+        let pool = test_pool().await;
+        initialise_week_with(&pool, Challenge::Glyph, 1, 0, &test_prompt("current"), day(0), day(7)).await.unwrap();
+        set_current_week_num_with(&pool, Challenge::Glyph, 1).await.unwrap();
+
+        let mut skipped = test_prompt("skipped");
+        skipped.skipped = true;
+        let queue = vec![
+            PromptData::new(Challenge::Glyph, "long", None, Some(2), None, None, None).unwrap(),
+            skipped,
+            PromptData::new(Challenge::Glyph, "fixed", None, None, None, None, Some("2024-06-01")).unwrap(),
+            test_prompt("last"),
+        ];
+        let forecast = forecast_queue_with(&pool, Challenge::Glyph, &queue).await.unwrap();
+
+        // Initialise and start each week the way the scheduler does.
+        for (idx, prompt) in queue.iter().enumerate().filter(|(_, p)| !p.skipped) {
+            let week_num = get_current_week_num_with(&pool, Challenge::Glyph).await.unwrap();
+            let current_week_info = get_week_info_with(&pool, week_num, Challenge::Glyph).await.unwrap();
+            let start_time = prompt.start_time_after(current_week_info.target_end_time);
+            let end_time = start_time + Challenge::Glyph.default_duration() * prompt.custom_duration.unwrap_or(1) as i32 - config().time_gap;
+            initialise_week_with(&pool, Challenge::Glyph, week_num + 1, idx as i64, prompt, start_time, end_time).await.unwrap();
+            rollover_week_with(&pool, Challenge::Glyph, week_num, start_time, vec![], NULL_TIMESTAMP).await.unwrap();
+
+            let week_info = get_week_info_with(&pool, week_num + 1, Challenge::Glyph).await.unwrap();
+            assert_eq!(forecast[idx], (week_info.week_num, week_info.target_start_time, week_info.target_end_time));
+        }
+
+        // The skipped prompt is forecast for the week it would have taken.
+        assert_eq!(forecast[1].0, forecast[2].0);
+        assert_eq!(forecast[2].1, Timestamp::try_from(1717200000).unwrap());
+    }
+
+    #[tokio::test]
+    async fn register_submission_twice() {
+        let pool = test_pool().await;
+        for expected in [true, false] {
+            let added = register_submission_with(&pool, MessageId::new(10), Challenge::Glyph, UserId::new(1), "link", 1).await.unwrap();
+            assert_eq!(added, expected);
+        }
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM submissions").fetch_one(&pool).await.unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn rank_winners_keeps_tally_order() {
+        let sub = |message, author, total| (MessageId::new(message), UserId::new(author), total);
+        let tally = vec![sub(10, 1, 2), sub(20, 2, 2), sub(30, 1, 1), sub(40, 3, 0)];
+        assert_eq!(rank_winners(tally), vec![sub(10, 1, 2), sub(20, 2, 2)]);
+    }
+
+    #[tokio::test]
+    async fn tally_breaks_ties_by_time_then_message() {
+        let pool = test_pool().await;
+        for (message, time) in [(10, 200), (20, 200), (30, 100)] {
+            sqlx::query("INSERT INTO submissions (message, week_num, challenge, author, link, time) VALUES (?, 1, 0, ?, '', ?)")
+                .bind(message)
+                .bind(message)
+                .bind(time)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        for idx in [0, 1, 2] { register_vote_with(&pool, Challenge::Glyph, 1, UserId::new(1), idx).await.unwrap(); }
+
+        let order: Vec<u64> = tally_week_with(&pool, Challenge::Glyph, 1).await.unwrap().into_iter().map(|(m, _, _)| m.get()).collect();
+        assert_eq!(order, vec![30, 10, 20]);
     }
-    let end_time = start_time + challenge.default_duration() * (prompt.custom_duration.unwrap_or(1) as i32);
-    Ok((week_num + position, start_time, end_time))
 }
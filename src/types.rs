@@ -1,25 +1,33 @@
 use std::{char, collections::HashMap, ops::{Add, AddAssign, Sub}, str::FromStr, sync::Arc};
 
-use chrono::{DateTime, Duration, TimeDelta, Utc};
-use poise::serenity_prelude::{prelude::TypeMapKey, ChannelId, Emoji, EmojiId, MessageId, ReactionType, UserId};
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeDelta, Utc};
+use poise::serenity_prelude::{prelude::TypeMapKey, ChannelId, Colour, Emoji, EmojiId, MessageId, ReactionType, RoleId, UserId};
+use serde::{Deserialize, Serialize};
 use sqlx::{prelude::FromRow, sqlite::SqliteRow};
 use tokio::sync::RwLock;
 
-use crate::{server_data::{AMBIGRAM_ANNOUNCEMENTS_CHANNEL_ID, AMBI_INTERVAL, GLYPH_ANNOUNCEMENTS_CHANNEL_ID, GLYPH_INTERVAL}, Error, ResT};
+use crate::{config::config, server_data::{format_ambi_announcement_spiel, format_glyph_announcement_spiel}, Error, Res, ResT};
 
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub struct MsgId(pub Option<MessageId>);
+/// A list of message ids, stored in the database as a JSON array.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MsgIds(pub Vec<MessageId>);
 
-impl From<Option<MessageId>> for MsgId {
-    fn from(value: Option<MessageId>) -> Self {
+impl From<Vec<MessageId>> for MsgIds {
+    fn from(value: Vec<MessageId>) -> Self {
         Self(value)
     }
 }
-impl TryFrom<i64> for MsgId {
-    type Error = ();
-    fn try_from(value: i64) -> Result<Self, Self::Error> {
-        Ok(Some(value as u64).filter(|x| *x != 0).map(|x| x.into()).into())
+impl TryFrom<String> for MsgIds {
+    type Error = Error;
+    fn try_from(value: String) -> ResT<Self> {
+        let ids: Vec<u64> = serde_json::from_str(&value)?;
+        Ok(Self(ids.into_iter().map(MessageId::new).collect()))
+    }
+}
+impl MsgIds {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.0.iter().map(|x| x.get()).collect::<Vec<u64>>()).unwrap()
     }
 }
 
@@ -73,10 +81,20 @@ impl TryFrom<Option<i64>> for Timestamp {
     }
 }
 
+impl Timestamp {
+    /// Format this as a Discord timestamp with the given style, e.g. `'F'` or `'R'`.
+    pub fn to_discord(&self, style: char) -> String {
+        match self.0 {
+            Some(t) => format!("<t:{}:{}>", t.timestamp(), style),
+            None => "unknown".to_owned(),
+        }
+    }
+}
+
 pub const NULL_TIMESTAMP: Timestamp = Timestamp(None);
 
 /// Data associated with a given glyph/ambi prompt
-#[derive(Clone, Debug, PartialEq, FromRow)]
+#[derive(Clone, Debug, PartialEq, FromRow, Serialize, Deserialize)]
 pub struct PromptData {
     #[sqlx(try_from="i8")]
     pub challenge: Challenge,
@@ -85,10 +103,74 @@ pub struct PromptData {
     pub custom_duration: Option<u16>,
     pub is_special: Option<bool>,
     pub extra_announcement_text: Option<String>,
+    /// Whether the scheduler should pass over this prompt for now.
+    #[serde(default)]
+    pub skipped: bool,
+    /// Unix timestamp before which this prompt must not start, even if the queue reaches it earlier.
+    #[serde(default)]
+    pub fixed_start_time: Option<i64>,
+}
+
+/// Maximum length of a prompt, in characters. Prompts are rendered into images and
+/// announcement messages, so anything much longer than this breaks their layout.
+pub const MAX_PROMPT_LENGTH: usize = 100;
+
+/// Trim a prompt, and check that it is neither empty nor too long.
+pub fn normalise_prompt_string(prompt_string: &str) -> ResT<String> {
+    let prompt_string = prompt_string.trim();
+    if prompt_string.is_empty() || prompt_string.chars().count() > MAX_PROMPT_LENGTH {
+        return Err(format!("Prompt must not be empty and contain at most {MAX_PROMPT_LENGTH} characters.").into());
+    }
+    Ok(prompt_string.to_owned())
+}
+
+/// Parse a date given as `YYYY-MM-DD` into the unix timestamp of midnight UTC on that day.
+pub fn parse_start_date(date: &str) -> ResT<i64> {
+    NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d")
+        .map(|d| d.and_time(NaiveTime::MIN).and_utc().timestamp())
+        .map_err(|_| format!("‘{}’ is not a date of the form YYYY-MM-DD.", date.trim()).into())
+}
+
+impl PromptData {
+    /// Build a new, not yet skipped prompt from user input, normalising the prompt string
+    /// and dropping modifiers that are the same as the default. Errors if the result is invalid.
+    pub fn new(challenge: Challenge, prompt_string: &str, size_percentage: Option<u16>, custom_duration: Option<u16>,
+               is_special: Option<bool>, extra_announcement_text: Option<String>, fixed_start_date: Option<&str>) -> ResT<Self> {
+        let prompt_data = PromptData {
+            challenge,
+            prompt_string: normalise_prompt_string(prompt_string)?,
+            size_percentage: size_percentage.filter(|x| x != &100),
+            custom_duration,
+            is_special: is_special.filter(|x| x == &true),
+            extra_announcement_text,
+            skipped: false,
+            fixed_start_time: fixed_start_date.map(parse_start_date).transpose()?,
+        };
+        prompt_data.validate()?;
+        Ok(prompt_data)
+    }
+
+    /// When the week for this prompt starts if the week before it ends at `previous_end_time`.
+    /// Normally this is the configured time gap later, but never before the fixed start time.
+    pub fn start_time_after(&self, previous_end_time: Timestamp) -> Timestamp {
+        let start_time = previous_end_time + config().time_gap;
+        match self.fixed_start_time.and_then(|t| Timestamp::try_from(t).ok()) {
+            Some(fixed) if fixed > start_time => fixed,
+            _ => start_time,
+        }
+    }
+
+    /// Check that none of the properties have nonsensical values.
+    pub fn validate(&self) -> Res {
+        normalise_prompt_string(&self.prompt_string)?;
+        if let Some(0) = self.size_percentage { return Err("Cannot set size_percentage to 0.".into()); }
+        if let Some(0) = self.custom_duration { return Err("Cannot set custom_duration to 0.".into()); }
+        Ok(())
+    }
 }
 
 /// What challenge a submission belongs to.
-#[derive(Copy, Clone, Debug, PartialEq, poise::ChoiceParameter)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, poise::ChoiceParameter, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum Challenge {
     Glyph = 0,
@@ -96,6 +178,12 @@ pub enum Challenge {
 }
 
 impl Challenge {
+    /// Every challenge. Anything that has to be done for each challenge should iterate over
+    /// this, so that adding a challenge only means adding a variant.
+    pub fn all() -> impl Iterator<Item = Challenge> {
+        [Challenge::Glyph, Challenge::Ambigram].into_iter()
+    }
+
     pub fn raw(self) -> u8 {
         self as _
     }
@@ -121,21 +209,78 @@ impl Challenge {
         }
     }
 
+    /// The challenge whose [`one_char_name`](Challenge::one_char_name) is `c`, if any.
+    pub fn from_one_char_name(c: char) -> Option<Challenge> {
+        Challenge::all().find(|challenge| challenge.one_char_name() == c)
+    }
+
     pub fn name_to_path(s: &str) -> String {
         format!("./generation/{}.png", s)
     }
 
     pub fn default_duration(&self) -> Duration {
         match self {
-            Challenge::Glyph => GLYPH_INTERVAL,
-            Challenge::Ambigram => AMBI_INTERVAL,
+            Challenge::Glyph => config().glyph_interval,
+            Challenge::Ambigram => config().ambi_interval,
         }
     }
 
     pub fn announcement_channel(&self) -> ChannelId {
         match self {
-            Challenge::Glyph => GLYPH_ANNOUNCEMENTS_CHANNEL_ID,
-            Challenge::Ambigram => AMBIGRAM_ANNOUNCEMENTS_CHANNEL_ID
+            Challenge::Glyph => config().glyph_announcements_channel_id,
+            Challenge::Ambigram => config().ambigram_announcements_channel_id
+        }
+    }
+
+    /// The role to ping when a new week of this challenge is announced, if any.
+    pub fn announcement_role(&self) -> Option<RoleId> {
+        match self {
+            Challenge::Glyph => config().glyph_announcement_role_id,
+            Challenge::Ambigram => config().ambigram_announcement_role_id
+        }
+    }
+
+    /// Whether animated submissions to this challenge keep their animation.
+    pub fn allows_animation(&self) -> bool {
+        match self {
+            Challenge::Glyph => config().glyph_allow_animated,
+            Challenge::Ambigram => config().ambigram_allow_animated
+        }
+    }
+
+    /// How long before the deadline to remind participants of it, if at all.
+    pub fn reminder_lead_time(&self) -> Option<Duration> {
+        match self {
+            Challenge::Glyph => config().glyph_reminder_lead_time,
+            Challenge::Ambigram => config().ambigram_reminder_lead_time
+        }
+    }
+
+    pub fn submission_channel(&self) -> ChannelId {
+        match self {
+            Challenge::Glyph => config().glyph_submission_channel_id,
+            Challenge::Ambigram => config().ambigram_submission_channel_id
+        }
+    }
+
+    /// The challenge whose submissions are posted in `channel`, if any.
+    pub fn from_submission_channel(channel: ChannelId) -> Option<Challenge> {
+        Challenge::all().find(|challenge| challenge.submission_channel() == channel)
+    }
+
+    /// The text accompanying the announcement of a week of this challenge.
+    pub fn format_announcement_spiel(&self, week_num: i64, prompt_string: &str, full_timestamp: &str, relative_timestamp: &str) -> String {
+        match self {
+            Challenge::Glyph => format_glyph_announcement_spiel(week_num, prompt_string, full_timestamp, relative_timestamp),
+            Challenge::Ambigram => format_ambi_announcement_spiel(week_num, prompt_string, full_timestamp, relative_timestamp),
+        }
+    }
+
+    /// The colour of embeds about this challenge.
+    pub fn colour(&self) -> Colour {
+        match self {
+            Challenge::Glyph => config().glyph_embed_colour,
+            Challenge::Ambigram => config().ambigram_embed_colour,
         }
     }
     
@@ -144,22 +289,16 @@ impl Challenge {
 impl FromStr for Challenge {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "0" => Ok(Challenge::Glyph),
-            "1" => Ok(Challenge::Ambigram),
-            id => Err(format!("Unknown challenge ID '{:?}'", id).into()),
-        }
+        Challenge::all()
+            .find(|challenge| challenge.raw().to_string() == s)
+            .ok_or_else(|| format!("Unknown challenge ID '{:?}'", s).into())
     }
 }
 
 impl TryFrom<i8> for Challenge {
     type Error = ();
     fn try_from(i: i8) -> Result<Self, Self::Error> {
-        match i {
-            0 => Ok(Challenge::Glyph),
-            1 => Ok(Challenge::Ambigram),
-            _ => Err(()),
-        }
+        Challenge::all().find(|challenge| challenge.raw() as i8 == i).ok_or(())
     }
 }
 
@@ -211,6 +350,26 @@ pub struct UserProfileData {
     pub ambigrams_submissions: i64,
 }
 
+/// Totals across all weeks of a challenge.
+#[derive(Clone, Debug)]
+pub struct ChallengeStats {
+    /// Number of weeks that have started.
+    pub weeks: i64,
+    pub submissions: i64,
+
+    /// Number of distinct users who have submitted anything.
+    pub participants: i64,
+
+    /// The week with the most submissions and how many it had, if there are any submissions.
+    pub busiest_week: Option<(i64, i64)>,
+}
+
+impl ChallengeStats {
+    pub fn average_submissions(&self) -> f64 {
+        if self.weeks == 0 { 0.0 } else { self.submissions as f64 / self.weeks as f64 }
+    }
+}
+
 #[derive(Clone, Debug, FromRow)]
 pub struct WeekInfo {
     #[sqlx(try_from="i8")]
@@ -228,10 +387,21 @@ pub struct WeekInfo {
     pub actual_end_time: Timestamp,
     pub is_special: bool,
     pub num_subs: i64,
-    #[sqlx(try_from="i64")]
-    pub poll_message_id: MsgId,
-    #[sqlx(try_from="i64")]
-    pub second_poll_message_id: MsgId,
+    #[sqlx(try_from="String")]
+    pub poll_message_ids: MsgIds,
+    /// When voting on this week's submissions is due to close, if it has its own voting window
+    /// rather than lasting until the end of the next week.
+    #[sqlx(try_from="Option<i64>")]
+    pub target_voting_end_time: Timestamp,
+    #[sqlx(try_from="Option<i64>")]
+    pub actual_voting_end_time: Timestamp,
+}
+
+impl WeekInfo {
+    /// Whether the poll for this week has been posted and votes can still be cast in it.
+    pub fn voting_open(&self) -> bool {
+        !self.poll_message_ids.0.is_empty() && self.actual_voting_end_time == NULL_TIMESTAMP
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -242,6 +412,15 @@ pub enum WinnerPosition {
 }
 
 impl WinnerPosition {
+    /// Index of this position in a ranking sorted by descending vote count.
+    pub fn index(&self) -> usize {
+        match self {
+            Self::First => 0,
+            Self::Second => 1,
+            Self::Third => 2
+        }
+    }
+
     pub fn name(&self) -> String {
         match self {
             Self::First => "first".to_owned(),
@@ -269,6 +448,24 @@ impl ChallengeImageOptions {
     }
 }
 
+/// How to combine imported prompts with an existing queue.
+#[derive(Copy, Clone, Debug, poise::ChoiceParameter)]
+pub enum ImportMode {
+    #[name="append"]
+    Append,
+    #[name="replace"]
+    Replace,
+}
+
+/// What to rank users by on the leaderboard.
+#[derive(Copy, Clone, Debug, poise::ChoiceParameter)]
+pub enum LeaderboardMetric {
+    #[name="first_places"]
+    FirstPlaces,
+    #[name="submissions"]
+    Submissions,
+}
+
 /// The types of image which we might want to preview.
 #[derive(Clone, Debug, poise::ChoiceParameter)]
 pub enum PreviewableImages {
@@ -284,6 +481,7 @@ pub enum PreviewableImages {
     ThirdPlace,
 }
 
+/// The types of image which can be replaced with an uploaded one.
 #[derive(Clone, Debug, poise::ChoiceParameter)]
 pub enum UploadableImages {
     #[name="next_challenge_announcement"]
@@ -292,6 +490,16 @@ pub enum UploadableImages {
     Poll,
 }
 
+impl UploadableImages {
+    /// Matches [`ChallengeImageOptions::suffix`] for the corresponding image.
+    pub fn suffix(&self) -> String {
+        match self {
+            Self::Announcement => "announcement".to_owned(),
+            Self::Poll => "poll".to_owned(),
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum AnyEmoji {
     Default(char),
@@ -330,9 +538,62 @@ pub enum UserVoteReplyStatus {
     Responding
 }
 
+/// Returned by [`register_vote`](crate::sql::register_vote) when a user tries to vote for
+/// more submissions than they are allowed to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoteLimitError {
+    pub used: i64,
+    pub limit: i64,
+}
+
+impl std::fmt::Display for VoteLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "You have already used {} of your {} votes. Remove a vote before adding another.", self.used, self.limit)
+    }
+}
+
+impl std::error::Error for VoteLimitError {}
+
+/// Returned by [`download_submission`](crate::file::download_submission) when the
+/// contents of a submission turn out not to be an image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NotAnImageError {
+    /// The MIME type of the contents, if it could be determined.
+    pub detected: Option<&'static str>,
+}
+
+impl std::fmt::Display for NotAnImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.detected {
+            Some(mime_type) => write!(f, "Submissions must contain only images, but this is a file of type `{}`", mime_type),
+            None => write!(f, "Submissions must contain only images, but this is not a recognised image format"),
+        }
+    }
+}
+
+impl std::error::Error for NotAnImageError {}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct UserVoteStatusData;
 
 impl TypeMapKey for UserVoteStatusData {
     type Value = Arc<RwLock<HashMap<UserId, UserVoteReplyStatus>>>;
+}
+
+/// A submission that its author removed recently, and which they can still restore.
+#[derive(Clone, Debug)]
+pub struct RemovedSubmission {
+    pub challenge: Challenge,
+    pub week_num: i64,
+    pub author: UserId,
+    pub channel: ChannelId,
+    /// Unix timestamp in milliseconds after which the removal can no longer be undone.
+    pub expires: i64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RemovedSubmissionData;
+
+impl TypeMapKey for RemovedSubmissionData {
+    type Value = Arc<RwLock<HashMap<MessageId, RemovedSubmission>>>;
 }
\ No newline at end of file